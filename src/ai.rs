@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+use chess_network_protocol::{Move, Piece};
+
+use crate::rules::{self, Kind, Position};
+
+const MATE: i32 = 1_000_000;
+
+pub struct SearchConfig {
+    pub depth: u32,
+    pub time_budget: Duration
+}
+
+struct Search {
+    deadline: Instant,
+    aborted: bool
+}
+
+impl SearchConfig {
+    pub fn new() -> SearchConfig {
+        return SearchConfig { depth: 4, time_budget: Duration::from_millis(2000) };
+    }
+}
+
+fn value(kind: Kind) -> i32 {
+    return match kind {
+        Kind::Pawn => 100,
+        Kind::Knight => 320,
+        Kind::Bishop => 330,
+        Kind::Rook => 500,
+        Kind::Queen => 900,
+        Kind::King => 0
+    };
+}
+
+// Material plus a small bonus for advanced pawns and centralised minor
+// pieces, seen from the side to move.
+pub fn evaluate(position: &Position) -> i32 {
+    let mut score = 0;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let p = position.board[y][x];
+            let Some(k) = rules::kind(p) else { continue; };
+            let white = rules::is_white(p);
+
+            let centre = 6 - (2 * x as i32 - 7).abs() / 2 - (2 * y as i32 - 7).abs() / 2;
+            let advance = if white { y as i32 } else { 7 - y as i32 };
+
+            let bonus = match k {
+                Kind::Pawn => advance * 5,
+                Kind::Knight | Kind::Bishop => centre * 4,
+                _ => 0
+            };
+
+            let total = value(k) + bonus;
+            score += if white { total } else { -total };
+        }
+    }
+
+    return if position.white_to_move { score } else { -score };
+}
+
+// Captures of valuable pieces first, which makes alpha-beta cut much earlier.
+fn order(position: &Position, moves: &mut Vec<Move>) {
+    moves.sort_by_key(|m| {
+        let victim = rules::kind(position.board[m.end_y][m.end_x]).map(value).unwrap_or(0);
+        let attacker = rules::kind(position.board[m.start_y][m.start_x]).map(value).unwrap_or(0);
+        let promotion = if m.promotion != Piece::None { rules::kind(m.promotion).map(value).unwrap_or(0) } else { 0 };
+        return -(victim * 10 - attacker / 10 + promotion);
+    });
+}
+
+impl Search {
+    fn negamax(&mut self, position: &Position, depth: u32, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        if Instant::now() >= self.deadline {
+            self.aborted = true;
+            return 0;
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return if position.in_check() { -MATE + ply } else { 0 };
+        }
+
+        if depth == 0 {
+            return evaluate(position);
+        }
+
+        order(position, &mut moves);
+
+        for m in moves {
+            let mut next = position.clone();
+            next.apply(&m);
+
+            let score = -self.negamax(&next, depth - 1, ply + 1, -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+
+            if score >= beta {
+                return beta;
+            }
+
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        return alpha;
+    }
+}
+
+// Iterative deepening so there is always a move from the last finished depth
+// when the time budget runs out. The search only considers `root_moves`,
+// which lets the caller restrict it to what the playing engine accepts.
+pub fn best_move(position: &Position, root_moves: &[Move], config: &SearchConfig) -> Option<Move> {
+    let mut search = Search { deadline: Instant::now() + config.time_budget, aborted: false };
+    let mut moves = root_moves.to_vec();
+    order(position, &mut moves);
+
+    let mut best = moves.first().cloned();
+
+    for depth in 1..=config.depth.max(1) {
+        let mut alpha = -MATE - 1;
+        let mut depth_best = None;
+
+        for m in &moves {
+            let mut next = position.clone();
+            next.apply(m);
+
+            let score = -search.negamax(&next, depth - 1, 1, -MATE - 1, -alpha);
+            if search.aborted {
+                break;
+            }
+
+            if score > alpha {
+                alpha = score;
+                depth_best = Some(m.clone());
+            }
+        }
+
+        if search.aborted {
+            break;
+        }
+
+        if let Some(m) = depth_best {
+            // Search the best move first at the next depth.
+            moves.retain(|other| *other != m);
+            moves.insert(0, m.clone());
+            best = Some(m);
+        }
+    }
+
+    return best;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(pieces: &[(&str, Piece)], white_to_move: bool) -> Position {
+        let mut board = [[Piece::None; 8]; 8];
+
+        for (square, piece) in pieces {
+            let s = square.as_bytes();
+            board[(s[1] - b'1') as usize][(s[0] - b'a') as usize] = *piece;
+        }
+
+        return Position::from_board(board, white_to_move);
+    }
+
+    fn search(position: &Position, depth: u32) -> Option<String> {
+        let config = SearchConfig { depth: depth, time_budget: Duration::from_secs(10) };
+        return best_move(position, &position.legal_moves(), &config).map(|m| rules::move_name(&m));
+    }
+
+    #[test]
+    fn mates_in_one() {
+        let p = position(&[
+            ("g1", Piece::WhiteKing), ("a1", Piece::WhiteRook),
+            ("g8", Piece::BlackKing), ("f7", Piece::BlackPawn), ("g7", Piece::BlackPawn), ("h7", Piece::BlackPawn)
+        ], true);
+
+        assert_eq!(search(&p, 2).as_deref(), Some("a1a8"));
+    }
+
+    #[test]
+    fn takes_a_hanging_queen() {
+        let p = position(&[("a1", Piece::WhiteKing), ("d1", Piece::WhiteRook), ("h8", Piece::BlackKing), ("d5", Piece::BlackQueen)], true);
+
+        assert_eq!(search(&p, 3).as_deref(), Some("d1d5"));
+    }
+
+    #[test]
+    fn respects_the_time_budget() {
+        let config = SearchConfig { depth: 30, time_budget: Duration::from_millis(100) };
+        let p = Position::new();
+        let start = Instant::now();
+
+        assert!(best_move(&p, &p.legal_moves(), &config).is_some());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
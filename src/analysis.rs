@@ -0,0 +1,178 @@
+use chess_network_protocol::{Move, Piece};
+use glm::*;
+
+use crate::model::Model2D;
+use crate::rules::{self, Board, Position};
+
+// Looking back at a finished game. The moves of the game are stepped through
+// one ply at a time, and moves tried on the board start a variation from the
+// ply shown. Nothing here is sent anywhere.
+pub struct Analysis {
+    moves: Vec<Move>,
+    ply: usize,
+    variation: Vec<Move>,
+    selected: Option<(usize, usize)>
+}
+
+// Moves as the other side sent them may carry a promotion piece on a move
+// that does not promote, or one of the wrong colour. Only the squares and
+// what a pawn turns into count.
+fn same_move(m: &Move, played: &Move) -> bool {
+    let squares = (m.start_x, m.start_y, m.end_x, m.end_y) == (played.start_x, played.start_y, played.end_x, played.end_y);
+    return squares && (m.promotion == Piece::None || rules::kind(m.promotion) == rules::kind(played.promotion));
+}
+
+impl Analysis {
+    // Starts on the final position.
+    pub fn new(moves: Vec<Move>) -> Analysis {
+        return Analysis { ply: moves.len(), moves: moves, variation: vec![], selected: None };
+    }
+
+    pub fn position(&self) -> Position {
+        let mut position = Position::new();
+
+        for m in self.moves[..self.ply].iter().chain(self.variation.iter()) {
+            position.apply(m);
+        }
+
+        return position;
+    }
+
+    pub fn board(&self) -> Board {
+        return self.position().board;
+    }
+
+    // Takes back the last move of the variation first, then the game's.
+    pub fn back(&mut self) {
+        self.selected = None;
+
+        if self.variation.pop().is_none() && self.ply > 0 {
+            self.ply -= 1;
+        }
+    }
+
+    // Only the game's own moves can be stepped forward through.
+    pub fn forward(&mut self) {
+        self.selected = None;
+
+        if self.variation.is_empty() && self.ply < self.moves.len() {
+            self.ply += 1;
+        }
+    }
+
+    pub fn first(&mut self) {
+        self.selected = None;
+        self.variation.clear();
+        self.ply = 0;
+    }
+
+    pub fn last(&mut self) {
+        self.selected = None;
+        self.variation.clear();
+        self.ply = self.moves.len();
+    }
+
+    // Selects a piece of the side to move, or plays the selected piece to
+    // the square. Pawns reaching the last rank become queens.
+    pub fn pick(&mut self, x: usize, y: usize) {
+        let position = self.position();
+
+        if let Some((sx, sy)) = self.selected.take() {
+            let found = position.legal_moves().into_iter().find(|m| {
+                (m.start_x, m.start_y, m.end_x, m.end_y) == (sx, sy, x, y)
+                    && (m.promotion == Piece::None || rules::kind(m.promotion) == Some(rules::Kind::Queen))
+            });
+
+            if let Some(m) = found {
+                // Playing the game's next move just steps forward.
+                if self.variation.is_empty() && self.moves.get(self.ply).is_some_and(|played| same_move(&m, played)) {
+                    self.ply += 1;
+                } else {
+                    self.variation.push(m);
+                }
+                return;
+            }
+        }
+
+        let piece = position.board[y][x];
+        if piece != Piece::None && rules::is_white(piece) == position.white_to_move {
+            self.selected = Some((x, y));
+        }
+    }
+
+    pub fn label(&self) -> String {
+        if !self.variation.is_empty() {
+            return format!("Variation, {} moves", self.variation.len());
+        }
+
+        return format!("Move {} of {}", self.ply, self.moves.len());
+    }
+
+    // Shows the move that led to the position and the selected piece with
+    // where it can go.
+    pub fn colour(&self, board: &mut [Model2D; 64]) {
+        for tile in board.iter_mut() {
+            tile.color = tile.default_color;
+        }
+
+        let shown = if self.variation.is_empty() { self.ply.checked_sub(1).map(|i| &self.moves[i]) } else { self.variation.last() };
+
+        if let Some(m) = shown {
+            for (x, y) in [(m.start_x, m.start_y), (m.end_x, m.end_y)] {
+                board[y*8 + x].color = board[y*8 + x].color + Vec4{ x: 0.25, y: 0.25, z: 0.0, w: 0.0 };
+            }
+        }
+
+        if let Some((sx, sy)) = self.selected {
+            board[sy*8 + sx].color = board[sy*8 + sx].color + Vec4{ x: 0.4, y: 0.4, z: 0.0, w: 0.0 };
+
+            for m in self.position().legal_moves().iter().filter(|m| (m.start_x, m.start_y) == (sx, sy)) {
+                board[m.end_y*8 + m.end_x].color = board[m.end_y*8 + m.end_x].color + Vec4{ x: 0.0, y: 0.4, z: 0.0, w: 0.0 };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_promotion(name: &str, promotion: Piece) -> Move {
+        let mut m = rules::parse_move(name, true).unwrap();
+        m.promotion = promotion;
+        return m;
+    }
+
+    #[test]
+    fn picking_the_game_move_steps_forward() {
+        // The client sent a queen along with a plain pawn move.
+        let moves = vec![with_promotion("e2e4", Piece::WhiteQueen), with_promotion("e7e5", Piece::None)];
+        let mut analysis = Analysis::new(moves);
+        analysis.first();
+
+        analysis.pick(4, 1);
+        analysis.pick(4, 3);
+
+        assert_eq!(analysis.label(), "Move 1 of 2");
+    }
+
+    #[test]
+    fn picking_another_move_starts_a_variation() {
+        let mut analysis = Analysis::new(vec![with_promotion("e2e4", Piece::None)]);
+        analysis.first();
+
+        analysis.pick(3, 1);
+        analysis.pick(3, 3);
+
+        assert_eq!(analysis.label(), "Variation, 1 moves");
+    }
+
+    #[test]
+    fn promotions_compare_the_piece() {
+        let queen = with_promotion("a7a8q", Piece::WhiteQueen);
+
+        assert!(same_move(&queen, &with_promotion("a7a8", Piece::BlackQueen)));
+        assert!(!same_move(&queen, &with_promotion("a7a8", Piece::WhiteKnight)));
+        assert!(same_move(&with_promotion("e2e4", Piece::None), &with_promotion("e2e4", Piece::WhiteRook)));
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::source::SineWave;
+use chess_network_protocol::{Move, Piece};
+
+use crate::rules::{self, Board};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sound {
+    Move,
+    Capture,
+    Castle,
+    Check,
+    Illegal,
+    GameOver
+}
+
+pub struct AudioConfig {
+    pub enabled: bool,
+    pub muted: bool,
+    pub volume: f32
+}
+
+pub struct Audio {
+    // The stream has to outlive every sink that plays on it.
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    pub muted: bool,
+    pub volume: f32
+}
+
+impl Sound {
+    // Each sound is a short sequence of (frequency, milliseconds) tones.
+    fn notes(&self) -> &'static [(f32, u64)] {
+        return match self {
+            Sound::Move => &[(660.0, 60)],
+            Sound::Capture => &[(440.0, 50), (330.0, 90)],
+            Sound::Castle => &[(523.0, 50), (659.0, 50)],
+            Sound::Check => &[(880.0, 80), (880.0, 80)],
+            Sound::Illegal => &[(180.0, 160)],
+            Sound::GameOver => &[(523.0, 120), (392.0, 120), (262.0, 240)]
+        };
+    }
+
+    pub fn for_move(before: &Board, after: &Board, m: &Move) -> Sound {
+        let piece = before[m.start_y][m.start_x];
+        let white = rules::is_white(piece);
+
+        if rules::in_check(after, !white) {
+            return Sound::Check;
+        }
+
+        if (piece == Piece::WhiteKing || piece == Piece::BlackKing) && m.start_x.abs_diff(m.end_x) == 2 {
+            return Sound::Castle;
+        }
+
+        let en_passant = (piece == Piece::WhitePawn || piece == Piece::BlackPawn) && m.start_x != m.end_x;
+        if before[m.end_y][m.end_x] != Piece::None || en_passant {
+            return Sound::Capture;
+        }
+
+        return Sound::Move;
+    }
+}
+
+impl AudioConfig {
+    pub fn new() -> AudioConfig {
+        return AudioConfig { enabled: true, muted: false, volume: 0.3 };
+    }
+}
+
+impl Audio {
+    pub fn new(config: &AudioConfig) -> Audio {
+        let output = if config.enabled {
+            match OutputStream::try_default() {
+                Ok(output) => Some(output),
+                Err(e) => {
+                    eprintln!("No audio output available, sound is disabled: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        return Audio { output: output, muted: config.muted, volume: config.volume };
+    }
+
+    pub fn available(&self) -> bool {
+        return self.output.is_some();
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    pub fn play(&self, sound: Sound) {
+        if self.muted {
+            return;
+        }
+
+        let Some((_, handle)) = &self.output else { return; };
+        let Ok(sink) = Sink::try_new(handle) else { return; };
+
+        for &(freq, ms) in sound.notes() {
+            sink.append(SineWave::new(freq).take_duration(Duration::from_millis(ms)).amplify(self.volume));
+        }
+
+        sink.detach();
+    }
+}
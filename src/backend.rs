@@ -0,0 +1,202 @@
+use chess_network_protocol::{Features, Joever, Move, Piece};
+
+use crate::board::{engine_piece, protocol_piece, BoardState};
+use crate::rules::{self, Board, Position};
+
+// Everything the host needs from a chess engine. Moves and boards use the
+// protocol types so the network and rendering code stay engine agnostic.
+pub trait ChessBackend: Send {
+    fn name(&self) -> &'static str;
+    fn board(&self) -> Board;
+    fn legal_moves(&mut self) -> Vec<Move>;
+    // Plays the move if it is legal. A missing promotion piece means queen.
+    fn play(&mut self, m: &Move) -> bool;
+    fn result(&mut self) -> Joever;
+    // Whether the side to move is in check.
+    fn in_check(&mut self) -> bool;
+    // An engine of the same kind in the starting position.
+    fn new_game(&self) -> Box<dyn ChessBackend>;
+    // The rules it plays by, as the host advertises them in its handshake.
+    fn features(&self) -> Vec<Features>;
+
+    fn moves_from(&mut self, x: usize, y: usize) -> Vec<Move> {
+        return self.legal_moves().into_iter().filter(|m| (m.start_x, m.start_y) == (x, y)).collect();
+    }
+}
+
+pub fn from_name(name: &str) -> Option<Box<dyn ChessBackend>> {
+    return match name {
+        "ludviggl" => Some(Box::new(LudvigglBackend::new())),
+        "builtin" => Some(Box::new(BuiltinBackend::new())),
+        _ => None
+    };
+}
+
+pub struct LudvigglBackend {
+    chess: ludviggl_chess::Game,
+    // Tracked here rather than through the engine's player type.
+    white_to_move: bool
+}
+
+pub struct BuiltinBackend {
+    position: Position
+}
+
+impl LudvigglBackend {
+    pub fn new() -> LudvigglBackend {
+        return LudvigglBackend { chess: ludviggl_chess::Game::new(), white_to_move: true };
+    }
+}
+
+impl BuiltinBackend {
+    pub fn new() -> BuiltinBackend {
+        return BuiltinBackend { position: Position::new() };
+    }
+}
+
+impl ChessBackend for LudvigglBackend {
+    fn name(&self) -> &'static str {
+        return "ludviggl-chess";
+    }
+
+    fn board(&self) -> Board {
+        return BoardState::from_engine(self.chess.get_white_positions(), self.chess.get_black_positions()).to_protocol();
+    }
+
+    // Selecting a piece and then its own square is never a legal move, which
+    // puts the engine back into piece selection without changing the position.
+    fn legal_moves(&mut self) -> Vec<Move> {
+        let mut moves = vec![];
+        let chess = &mut self.chess;
+
+        if !matches!(chess.get_state(), ludviggl_chess::State::SelectPiece) {
+            return moves;
+        }
+
+        let pieces: Vec<(ludviggl_chess::Piece, u8, u8, bool)> = chess.get_white_positions().iter().map(|&(p, x, y)| (p, x, y, true))
+            .chain(chess.get_black_positions().iter().map(|&(p, x, y)| (p, x, y, false)))
+            .collect();
+
+        for (p, x, y, white) in pieces {
+            if chess.select_piece(x, y).is_err() || !matches!(chess.get_state(), ludviggl_chess::State::SelectMove) {
+                continue;
+            }
+
+            let targets = chess.get_moves().map(|m| m.to_vec()).unwrap_or_default();
+            let _ = chess.select_move(x, y);
+
+            for (a, b) in targets {
+                let (start_x, start_y, end_x, end_y) = (x as usize, y as usize, a as usize, b as usize);
+
+                if matches!(p, ludviggl_chess::Piece::Pawn) && (b == 0 || b == 7) {
+                    for promotion in [ludviggl_chess::Piece::Queen, ludviggl_chess::Piece::Rook, ludviggl_chess::Piece::Bishop, ludviggl_chess::Piece::Knight] {
+                        moves.push(Move{ start_x, start_y, end_x, end_y, promotion: protocol_piece(promotion, white) });
+                    }
+                } else {
+                    moves.push(Move{ start_x, start_y, end_x, end_y, promotion: Piece::None });
+                }
+            }
+        }
+
+        return moves;
+    }
+
+    // Only moves the engine listed are driven through it. Anything else could
+    // leave it halfway through a move, refusing every move after.
+    fn play(&mut self, m: &Move) -> bool {
+        let legal = self.legal_moves().iter().any(|l| (l.start_x, l.start_y, l.end_x, l.end_y) == (m.start_x, m.start_y, m.end_x, m.end_y));
+        if !legal {
+            return false;
+        }
+
+        let chess = &mut self.chess;
+        let (x, y) = (m.start_x as u8, m.start_y as u8);
+
+        if chess.select_piece(x, y).is_err() || !matches!(chess.get_state(), ludviggl_chess::State::SelectMove) {
+            return false;
+        }
+
+        let p_player = chess.get_current_player() as i8;
+        if chess.select_move(m.end_x as u8, m.end_y as u8).is_err() {
+            let _ = chess.select_move(x, y);
+            return false;
+        }
+
+        if matches!(chess.get_state(), ludviggl_chess::State::SelectPromotion) {
+            let promotion = match engine_piece(m.promotion) {
+                Some((p, _)) if !matches!(p, ludviggl_chess::Piece::Pawn | ludviggl_chess::Piece::King) => p,
+                _ => ludviggl_chess::Piece::Queen
+            };
+            // Left waiting for a piece the engine would refuse every move
+            // after, so a piece it does not take falls back to a queen.
+            if chess.select_promotion(promotion).is_err() && chess.select_promotion(ludviggl_chess::Piece::Queen).is_err() {
+                return false;
+            }
+        } else if p_player == chess.get_current_player() as i8 {
+            return false;
+        }
+
+        self.white_to_move = !self.white_to_move;
+        return true;
+    }
+
+    fn result(&mut self) -> Joever {
+        if matches!(self.chess.get_state(), ludviggl_chess::State::CheckMate) {
+            return if self.white_to_move { Joever::Black } else { Joever::White };
+        }
+
+        if self.legal_moves().is_empty() {
+            return Joever::Draw;
+        }
+
+        return Joever::Ongoing;
+    }
+
+    // The engine only tells about checkmate, so check is read off its board.
+    fn in_check(&mut self) -> bool {
+        return rules::in_check(&self.board(), self.white_to_move);
+    }
+
+    fn new_game(&self) -> Box<dyn ChessBackend> {
+        return Box::new(LudvigglBackend::new());
+    }
+
+    // Stalemate is told apart from mate here, by the engine leaving no moves.
+    fn features(&self) -> Vec<Features> {
+        return vec![Features::EnPassant, Features::Castling, Features::Promotion, Features::Stalemate, Features::PossibleMoveGeneration];
+    }
+}
+
+impl ChessBackend for BuiltinBackend {
+    fn name(&self) -> &'static str {
+        return "builtin";
+    }
+
+    fn board(&self) -> Board {
+        return self.position.board;
+    }
+
+    fn legal_moves(&mut self) -> Vec<Move> {
+        return self.position.legal_moves();
+    }
+
+    fn play(&mut self, m: &Move) -> bool {
+        return self.position.play(m);
+    }
+
+    fn result(&mut self) -> Joever {
+        return self.position.result();
+    }
+
+    fn in_check(&mut self) -> bool {
+        return self.position.in_check();
+    }
+
+    fn new_game(&self) -> Box<dyn ChessBackend> {
+        return Box::new(BuiltinBackend::new());
+    }
+
+    fn features(&self) -> Vec<Features> {
+        return vec![Features::EnPassant, Features::Castling, Features::Promotion, Features::Stalemate, Features::PossibleMoveGeneration];
+    }
+}
@@ -0,0 +1,201 @@
+use chess_network_protocol::Piece;
+
+use crate::rules::Board;
+
+// The board as everything in the game sees it: the host's engine, the client's
+// copy of the server state and the renderer. Squares are indexed [y][x] like
+// the protocol's board, with white starting on the low ranks.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoardState {
+    squares: Board
+}
+
+pub fn protocol_piece(piece: ludviggl_chess::Piece, white: bool) -> Piece {
+    return match (piece, white) {
+        (ludviggl_chess::Piece::Pawn, true) => { Piece::WhitePawn }
+        (ludviggl_chess::Piece::Rook, true) => { Piece::WhiteRook }
+        (ludviggl_chess::Piece::Knight, true) => { Piece::WhiteKnight }
+        (ludviggl_chess::Piece::Bishop, true) => { Piece::WhiteBishop }
+        (ludviggl_chess::Piece::King, true) => { Piece::WhiteKing }
+        (ludviggl_chess::Piece::Queen, true) => { Piece::WhiteQueen }
+        (ludviggl_chess::Piece::Pawn, false) => { Piece::BlackPawn }
+        (ludviggl_chess::Piece::Rook, false) => { Piece::BlackRook }
+        (ludviggl_chess::Piece::Knight, false) => { Piece::BlackKnight }
+        (ludviggl_chess::Piece::Bishop, false) => { Piece::BlackBishop }
+        (ludviggl_chess::Piece::King, false) => { Piece::BlackKing }
+        (ludviggl_chess::Piece::Queen, false) => { Piece::BlackQueen }
+    };
+}
+
+// The piece and whether it is white, None for an empty square.
+pub fn engine_piece(piece: Piece) -> Option<(ludviggl_chess::Piece, bool)> {
+    return match piece {
+        Piece::WhitePawn => Some((ludviggl_chess::Piece::Pawn, true)),
+        Piece::WhiteRook => Some((ludviggl_chess::Piece::Rook, true)),
+        Piece::WhiteKnight => Some((ludviggl_chess::Piece::Knight, true)),
+        Piece::WhiteBishop => Some((ludviggl_chess::Piece::Bishop, true)),
+        Piece::WhiteQueen => Some((ludviggl_chess::Piece::Queen, true)),
+        Piece::WhiteKing => Some((ludviggl_chess::Piece::King, true)),
+        Piece::BlackPawn => Some((ludviggl_chess::Piece::Pawn, false)),
+        Piece::BlackRook => Some((ludviggl_chess::Piece::Rook, false)),
+        Piece::BlackKnight => Some((ludviggl_chess::Piece::Knight, false)),
+        Piece::BlackBishop => Some((ludviggl_chess::Piece::Bishop, false)),
+        Piece::BlackQueen => Some((ludviggl_chess::Piece::Queen, false)),
+        Piece::BlackKing => Some((ludviggl_chess::Piece::King, false)),
+        Piece::None => None
+    };
+}
+
+// The sprite sheet has the pieces in ludviggl_chess's order.
+pub fn sprite_index(piece: Piece) -> Option<(bool, usize)> {
+    let (piece, white) = engine_piece(piece)?;
+
+    let index = match piece {
+        ludviggl_chess::Piece::Pawn => 0,
+        ludviggl_chess::Piece::Rook => 1,
+        ludviggl_chess::Piece::Knight => 2,
+        ludviggl_chess::Piece::Bishop => 3,
+        ludviggl_chess::Piece::Queen => 4,
+        ludviggl_chess::Piece::King => 5
+    };
+
+    return Some((white, index));
+}
+
+impl BoardState {
+    pub fn new() -> BoardState {
+        return BoardState { squares: [[Piece::None; 8]; 8] };
+    }
+
+    pub fn from_protocol(board: Board) -> BoardState {
+        return BoardState { squares: board };
+    }
+
+    pub fn to_protocol(&self) -> Board {
+        return self.squares;
+    }
+
+    pub fn from_engine(white: &[(ludviggl_chess::Piece, u8, u8)], black: &[(ludviggl_chess::Piece, u8, u8)]) -> BoardState {
+        let mut state = BoardState::new();
+
+        for &(p, x, y) in black {
+            state.squares[y as usize][x as usize] = protocol_piece(p, false);
+        }
+
+        for &(p, x, y) in white {
+            state.squares[y as usize][x as usize] = protocol_piece(p, true);
+        }
+
+        return state;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Piece {
+        return self.squares[y][x];
+    }
+
+    // Every piece on the board as (x, y, white, sprite index).
+    pub fn sprites(&self) -> Vec<(usize, usize, bool, usize)> {
+        let mut sprites = vec![];
+
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Some((white, index)) = sprite_index(self.squares[y][x]) {
+                    sprites.push((x, y, white, index));
+                }
+            }
+        }
+
+        return sprites;
+    }
+
+    // Squares whose contents differ between the two boards.
+    pub fn diff(&self, other: &BoardState) -> Vec<(usize, usize)> {
+        let mut squares = vec![];
+
+        for y in 0..8 {
+            for x in 0..8 {
+                if self.squares[y][x] != other.squares[y][x] {
+                    squares.push((x, y));
+                }
+            }
+        }
+
+        return squares;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECES: [Piece; 12] = [
+        Piece::WhitePawn, Piece::WhiteRook, Piece::WhiteKnight, Piece::WhiteBishop, Piece::WhiteQueen, Piece::WhiteKing,
+        Piece::BlackPawn, Piece::BlackRook, Piece::BlackKnight, Piece::BlackBishop, Piece::BlackQueen, Piece::BlackKing
+    ];
+
+    // In the sprite sheet's order.
+    const ENGINE_PIECES: [ludviggl_chess::Piece; 6] = [
+        ludviggl_chess::Piece::Pawn, ludviggl_chess::Piece::Rook, ludviggl_chess::Piece::Knight,
+        ludviggl_chess::Piece::Bishop, ludviggl_chess::Piece::Queen, ludviggl_chess::Piece::King
+    ];
+
+    #[test]
+    fn pieces_survive_the_engine() {
+        for piece in PIECES {
+            let (p, white) = engine_piece(piece).unwrap();
+            assert_eq!(protocol_piece(p, white), piece);
+        }
+
+        assert!(engine_piece(Piece::None).is_none());
+        assert!(sprite_index(Piece::None).is_none());
+    }
+
+    #[test]
+    fn engine_pieces_survive_the_protocol() {
+        for white in [true, false] {
+            for (index, p) in ENGINE_PIECES.into_iter().enumerate() {
+                let piece = protocol_piece(p, white);
+
+                assert_eq!(sprite_index(piece), Some((white, index)));
+                assert_eq!(engine_piece(piece).map(|(_, w)| w), Some(white));
+            }
+        }
+    }
+
+    #[test]
+    fn engine_lists_become_the_board() {
+        let white: Vec<(ludviggl_chess::Piece, u8, u8)> = ENGINE_PIECES.iter().enumerate().map(|(x, &p)| (p, x as u8, 0)).collect();
+        let black: Vec<(ludviggl_chess::Piece, u8, u8)> = ENGINE_PIECES.iter().enumerate().map(|(x, &p)| (p, x as u8, 7)).collect();
+        let state = BoardState::from_engine(&white, &black);
+
+        for (x, &p) in ENGINE_PIECES.iter().enumerate() {
+            assert_eq!(state.get(x, 0), protocol_piece(p, true));
+            assert_eq!(state.get(x, 7), protocol_piece(p, false));
+        }
+
+        assert_eq!(state.sprites().len(), 12);
+        assert_eq!(state.get(7, 0), Piece::None);
+        assert_eq!(BoardState::from_protocol(state.to_protocol()), state);
+    }
+
+    #[test]
+    fn diff_finds_every_changed_square() {
+        let empty = BoardState::new();
+        assert!(empty.diff(&empty).is_empty());
+
+        // Each piece on its own square, and a square that goes from one
+        // piece to another.
+        let mut board = [[Piece::None; 8]; 8];
+        for (i, &piece) in PIECES.iter().enumerate() {
+            board[i / 8][i % 8] = piece;
+        }
+        let full = BoardState::from_protocol(board);
+
+        let changed: Vec<(usize, usize)> = (0..12).map(|i| (i % 8, i / 8)).collect();
+        assert_eq!(empty.diff(&full), changed);
+        assert_eq!(full.diff(&empty), changed);
+
+        board[0][0] = Piece::BlackPawn;
+        assert_eq!(full.diff(&BoardState::from_protocol(board)), vec![(0, 0)]);
+    }
+}
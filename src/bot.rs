@@ -0,0 +1,258 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+
+use chess_network_protocol::*;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+
+use crate::ai::{self, SearchConfig};
+use crate::ext;
+use crate::heartbeat::{self, Heartbeat};
+use crate::net::{self, Outbox};
+use crate::record::Recorder;
+use crate::rules::Position;
+use crate::uci::UciEngine;
+use crate::ClientEvent;
+
+pub enum Strategy {
+    Random,
+    Greedy,
+    Search(SearchConfig),
+    Uci(UciEngine)
+}
+
+impl Strategy {
+    pub fn from_name(name: &str, config: SearchConfig) -> Option<Strategy> {
+        return match name {
+            "random" => Some(Strategy::Random),
+            "greedy" => Some(Strategy::Greedy),
+            "search" => Some(Strategy::Search(config)),
+            _ => None
+        };
+    }
+
+    pub fn choose(&mut self, position: &Position, candidates: &[Move]) -> Option<Move> {
+        let mut rng = rand::thread_rng();
+
+        return match self {
+            Strategy::Random => candidates.choose(&mut rng).cloned(),
+
+            Strategy::Greedy => {
+                let score = |m: &Move| {
+                    let mut next = position.clone();
+                    next.apply(m);
+                    return -ai::evaluate(&next);
+                };
+
+                let best = candidates.iter().map(score).max()?;
+                let top: Vec<&Move> = candidates.iter().filter(|m| score(m) == best).collect();
+                top.choose(&mut rng).map(|m| (*m).clone())
+            }
+
+            Strategy::Search(config) => ai::best_move(position, candidates, config),
+
+            Strategy::Uci(engine) => {
+                let mut answer = engine.best_move(position, &[]);
+
+                // The engine may not know about moves the playing side refused,
+                // so it is asked again with only the moves still open.
+                if let Ok(Some(m)) = &answer {
+                    if !candidates.contains(m) {
+                        eprintln!("{} wants {}, which is not available here.", engine.name, crate::rules::move_name(m));
+                        answer = engine.best_move(position, candidates);
+                    }
+                }
+
+                match answer {
+                    Ok(Some(m)) if candidates.contains(&m) => Some(m),
+
+                    Ok(Some(m)) => {
+                        eprintln!("{} still wants {}, giving up on it.", engine.name, crate::rules::move_name(&m));
+                        None
+                    }
+
+                    Ok(None) => None,
+
+                    Err(e) => {
+                        eprintln!("{} stopped answering: {}", engine.name, e);
+                        None
+                    }
+                }
+            }
+        };
+    }
+}
+
+struct Bot {
+    white: bool,
+    position: Position,
+    // Moves the server listed for us, if it generates them.
+    server_moves: Vec<Move>,
+    // Moves the server answered with an error since our last accepted move.
+    rejected: Vec<Move>,
+    strategy: Strategy
+}
+
+impl Bot {
+    fn candidates(&self) -> Vec<Move> {
+        let own = |m: &&Move| crate::rules::is_white(self.position.board[m.start_y][m.start_x]) == self.white;
+        let listed: Vec<Move> = self.server_moves.iter().filter(own).cloned().collect();
+        let moves = if listed.is_empty() { self.position.legal_moves() } else { listed };
+
+        return moves.into_iter().filter(|m| !self.rejected.contains(m)).collect();
+    }
+
+    fn sync(&mut self, board: [[Piece; 8]; 8], move_made: &Move, moves: Vec<Move>) {
+        // Applying the move keeps castling rights and en passant up to date,
+        // the server's board is still what counts.
+        self.position.apply(move_made);
+        self.position.board = board;
+        self.server_moves = moves;
+    }
+
+    fn play(&mut self, connection: &Connection) -> io::Result<()> {
+        self.position.white_to_move = self.white;
+
+        let candidates = self.candidates();
+        let message = match self.strategy.choose(&self.position, &candidates) {
+            Some(m) => {
+                println!("Bot plays ({}, {}) -> ({}, {}).", m.start_x, m.start_y, m.end_x, m.end_y);
+                self.rejected.push(m.clone());
+                ClientToServer::Move(m)
+            }
+
+            None => {
+                println!("Bot has no move left to try and resigns.");
+                ClientToServer::Resign
+            }
+        };
+
+        return connection.send(&message);
+    }
+}
+
+// The game's own connection for clients without a window, so TLS, passwords,
+// framing and heartbeats work the same. Reads block, but a host that agreed
+// on heartbeats and then goes quiet is given up on.
+pub struct Connection {
+    events: Receiver<ClientEvent>,
+    outbox: Outbox,
+    heartbeat: Heartbeat
+}
+
+impl Connection {
+    pub fn peer(&self) -> Option<SocketAddr> {
+        return self.outbox.peer();
+    }
+
+    pub fn send<T: Serialize>(&self, message: &T) -> io::Result<()> {
+        return self.outbox.send(message);
+    }
+
+    // The next message from the host. Pings are answered and every other
+    // extension is skipped, nothing here has a use for them.
+    pub fn read(&mut self) -> io::Result<ServerToClient> {
+        loop {
+            let event = match self.events.recv_timeout(heartbeat::INTERVAL) {
+                Ok(event) => event,
+
+                Err(RecvTimeoutError::Timeout) => {
+                    self.heartbeat.tick(&self.outbox);
+
+                    if self.heartbeat.expired() {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, format!("the host did not answer for {} seconds", self.heartbeat.timeout.as_secs())));
+                    }
+                    continue;
+                }
+
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the connection closed"));
+                }
+            };
+
+            self.heartbeat.heard();
+            self.heartbeat.tick(&self.outbox);
+
+            match event {
+                net::Event::Message(m) => { return Ok(m); }
+                net::Event::Extension(e) => { self.heartbeat.on_extension(&e, &self.outbox); }
+                net::Event::Closed(reason) => { return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason)); }
+                net::Event::Connected(_, _) => { }
+            }
+        }
+    }
+}
+
+// Connects like the game does, with the same timeouts on the connect and
+// the handshake.
+pub fn connect(address: &str, white: bool, tls: &Option<Arc<rustls::ClientConfig>>, password: &Option<String>, framing: bool, recorder: &Option<Recorder>, mut heartbeat: Heartbeat) -> io::Result<(Connection, ServerToClientHandshake)> {
+    let events = crate::spawn_connect(address, white, tls, password, framing, recorder);
+
+    return match events.recv() {
+        Ok(net::Event::Connected(des, outbox)) => {
+            heartbeat.enabled = ext::supports(&des.features, ext::HEARTBEAT);
+            heartbeat.heard();
+
+            Ok((Connection { events: events, outbox: outbox, heartbeat: heartbeat }, des))
+        }
+
+        Ok(net::Event::Closed(reason)) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason)),
+        _ => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the connection closed"))
+    };
+}
+
+// Plays a whole game as the client without opening a window.
+pub fn run(mut connection: Connection, des: ServerToClientHandshake, white: bool, strategy: Strategy) -> io::Result<()> {
+    println!("Connected to {}, playing {}.", connection.peer().map(|a| a.to_string()).unwrap_or_default(), if white { "white" } else { "black" });
+
+    let mut bot = Bot {
+        white: white,
+        position: Position::from_board(des.board, true),
+        server_moves: des.moves,
+        rejected: vec![],
+        strategy: strategy
+    };
+
+    if white {
+        bot.play(&connection)?;
+    }
+
+    loop {
+        match connection.read()? {
+            ServerToClient::State { board, moves, joever, move_made } => {
+                let ours = crate::rules::is_white(bot.position.board[move_made.start_y][move_made.start_x]) == white;
+                bot.sync(board, &move_made, moves);
+
+                if joever != Joever::Ongoing {
+                    println!("Game over: {:?}.", joever);
+                    return Ok(());
+                }
+
+                if ours {
+                    bot.rejected.clear();
+                } else {
+                    bot.play(&connection)?;
+                }
+            }
+
+            ServerToClient::Error { board, moves, joever: _, message } => {
+                println!("Server rejected the move: {}", message);
+                bot.position.board = board;
+                bot.server_moves = moves;
+                bot.play(&connection)?;
+            }
+
+            ServerToClient::Resigned { board: _, joever } => {
+                println!("Server resigned: {:?}.", joever);
+                return Ok(());
+            }
+
+            _ => {
+                println!("Game over: draw.");
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::io::{self, BufRead};
+
+use chess_network_protocol::*;
+
+use crate::bot::Connection;
+use crate::rules::{self, Position};
+
+// Lets a desktop chess GUI play a network game. The GUI talks to us as if we
+// were an engine: its moves are sent to the server as ours, and the moves the
+// server reports for the other side come back as the engine's replies.
+// Stdout belongs to the GUI, so everything else goes to stderr.
+pub enum Dialect {
+    Uci,
+    Xboard
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        return match name {
+            "uci" => Some(Dialect::Uci),
+            "xboard" | "cecp" => Some(Dialect::Xboard),
+            _ => None
+        };
+    }
+}
+
+enum Reply {
+    Moved(Move, bool),
+    Rejected(String),
+    Over
+}
+
+struct Bridge {
+    white: bool,
+    connection: Connection,
+    position: Position,
+    // Every move the server has accepted, ours and theirs.
+    played: Vec<String>,
+    joever: Joever
+}
+
+impl Bridge {
+    fn receive(&mut self) -> io::Result<Reply> {
+        match self.connection.read()? {
+            ServerToClient::State { board, moves: _, joever, move_made } => {
+                let ours = rules::is_white(self.position.board[move_made.start_y][move_made.start_x]) == self.white;
+
+                self.position.apply(&move_made);
+                self.position.board = board;
+                self.played.push(rules::move_name(&move_made));
+                self.joever = joever;
+
+                return Ok(Reply::Moved(move_made, ours));
+            }
+
+            ServerToClient::Error { board, moves: _, joever: _, message } => {
+                self.position.board = board;
+                return Ok(Reply::Rejected(message));
+            }
+
+            ServerToClient::Resigned { board: _, joever } => {
+                self.joever = joever;
+                return Ok(Reply::Over);
+            }
+
+            ServerToClient::Draw { board: _, moves: _ } => {
+                self.joever = Joever::Draw;
+                return Ok(Reply::Over);
+            }
+        }
+    }
+
+    // Sends one of our moves and waits until the server accepted or refused it.
+    fn play(&mut self, name: &str) -> io::Result<Result<(), String>> {
+        if self.joever != Joever::Ongoing {
+            return Ok(Err("the game is over".to_string()));
+        }
+
+        let Some(m) = rules::parse_move(name, self.white) else {
+            return Ok(Err(format!("\"{}\" is not a move", name)));
+        };
+
+        self.connection.send(&ClientToServer::Move(m))?;
+
+        loop {
+            match self.receive()? {
+                Reply::Moved(_, true) => { return Ok(Ok(())); }
+                Reply::Moved(_, false) => { }
+                Reply::Rejected(message) => { return Ok(Err(message)); }
+                Reply::Over => { return Ok(Err("the game is over".to_string())); }
+            }
+        }
+    }
+
+    // Waits for the other side's move, None once the game is over.
+    fn opponent_move(&mut self) -> io::Result<Option<Move>> {
+        while self.joever == Joever::Ongoing {
+            match self.receive()? {
+                Reply::Moved(m, false) => { return Ok(Some(m)); }
+                Reply::Over => { return Ok(None); }
+                _ => { }
+            }
+        }
+
+        return Ok(None);
+    }
+
+    fn our_turn(&self) -> bool {
+        return self.played.len() % 2 == if self.white { 0 } else { 1 };
+    }
+
+    fn result(&self) -> Option<&'static str> {
+        return match self.joever {
+            Joever::White => Some("1-0 {White wins}"),
+            Joever::Black => Some("0-1 {Black wins}"),
+            Joever::Draw => Some("1/2-1/2 {Draw}"),
+            _ => None
+        };
+    }
+}
+
+pub fn run(connection: Connection, des: ServerToClientHandshake, white: bool, dialect: Dialect) -> io::Result<()> {
+    eprintln!("Connected to {}, playing {}.", connection.peer().map(|a| a.to_string()).unwrap_or_default(), if white { "white" } else { "black" });
+
+    let mut bridge = Bridge {
+        white: white,
+        connection: connection,
+        position: Position::from_board(des.board, true),
+        played: vec![],
+        joever: des.joever
+    };
+
+    return match dialect {
+        Dialect::Uci => run_uci(&mut bridge),
+        Dialect::Xboard => run_xboard(&mut bridge)
+    };
+}
+
+fn run_uci(bridge: &mut Bridge) -> io::Result<()> {
+    // Moves from the last `position` command.
+    let mut gui_moves: Vec<String> = vec![];
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.first().copied() {
+            Some("uci") => {
+                println!("id name Network opponent");
+                println!("id author chess");
+                println!("uciok");
+            }
+
+            Some("isready") => { println!("readyok"); }
+
+            Some("position") => {
+                gui_moves = match words.iter().position(|w| *w == "moves") {
+                    Some(i) => words[i + 1..].iter().map(|w| w.to_string()).collect(),
+                    None => vec![]
+                };
+            }
+
+            Some("go") => {
+                // Whatever the GUI played since the last go is our move.
+                let mut rejected = None;
+                for name in gui_moves.iter().skip(bridge.played.len()) {
+                    if let Err(message) = bridge.play(name)? {
+                        rejected = Some(message);
+                        break;
+                    }
+                }
+
+                if let Some(message) = rejected {
+                    println!("info string server refused the move: {}", message);
+                    println!("bestmove 0000");
+                    continue;
+                }
+
+                if bridge.our_turn() && bridge.joever == Joever::Ongoing {
+                    println!("info string waiting for a move from the GUI");
+                    println!("bestmove 0000");
+                    continue;
+                }
+
+                match bridge.opponent_move()? {
+                    Some(m) => { println!("bestmove {}", rules::move_name(&m)); }
+
+                    None => {
+                        println!("info string game over {}", bridge.result().unwrap_or(""));
+                        println!("bestmove 0000");
+                    }
+                }
+            }
+
+            Some("quit") => { break; }
+
+            _ => { }
+        }
+    }
+
+    return Ok(());
+}
+
+fn run_xboard(bridge: &mut Bridge) -> io::Result<()> {
+    let mut force = false;
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let reply = match words.first().copied() {
+            Some("protover") => {
+                println!("feature myname=\"Network opponent\" usermove=1 setboard=0 sigint=0 sigterm=0 done=1");
+                false
+            }
+
+            Some("ping") => {
+                println!("pong {}", words.get(1).unwrap_or(&""));
+                false
+            }
+
+            Some("force") => {
+                force = true;
+                false
+            }
+
+            Some("go") => {
+                force = false;
+                true
+            }
+
+            Some("usermove") if words.len() > 1 => {
+                match bridge.play(words[1])? {
+                    Ok(()) => !force,
+
+                    Err(message) => {
+                        println!("Illegal move ({}): {}", message, words[1]);
+                        false
+                    }
+                }
+            }
+
+            Some("quit") => { break; }
+
+            _ => false
+        };
+
+        if reply && !bridge.our_turn() {
+            if let Some(m) = bridge.opponent_move()? {
+                println!("move {}", rules::move_name(&m));
+            }
+
+            if let Some(result) = bridge.result() {
+                println!("{}", result);
+            }
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use glfw::{Action, Key, WindowEvent};
+use glm::*;
+
+use crate::ext::{self, Extension};
+use crate::model::Model2D;
+use crate::net::Outbox;
+use crate::shader::Shader;
+use crate::{render_text, Character};
+
+const MAX_INPUT: usize = 80;
+const LINE_LEN: usize = 18;
+const VISIBLE_LINES: usize = 14;
+
+pub struct Chat {
+    pub enabled: bool,
+    pub focused: bool,
+    input: String,
+    lines: Vec<(bool, String)>,
+    peer_name: &'static str,
+    outbox: Option<Outbox>,
+    input_box: Model2D
+}
+
+impl Chat {
+    pub fn new(peer_name: &'static str) -> Chat {
+        let mut input_box = Model2D::white_tile();
+        input_box.color = vec4(0.2, 0.2, 0.15, 1.0);
+        input_box.default_color = input_box.color;
+        input_box.transform.translation = Vec2{ x: 5.0, y: -3.45 };
+        input_box.transform.scale = Vec2{ x: 1.9, y: 0.3 };
+
+        return Chat {
+            enabled: false,
+            focused: false,
+            input: String::new(),
+            lines: vec![],
+            peer_name: peer_name,
+            outbox: None,
+            input_box: input_box
+        };
+    }
+
+    pub fn connect(&mut self, outbox: &Outbox) {
+        self.outbox = Some(outbox.clone());
+        self.enabled = true;
+    }
+
+    pub fn on_extension(&mut self, extension: Extension, outbox: &Outbox) {
+        match extension {
+            Extension::Features(features) => {
+                if features.iter().any(|f| f == ext::CHAT) {
+                    self.connect(outbox);
+                }
+            }
+
+            Extension::Chat { message } => {
+                if self.enabled {
+                    self.push_message(false, &format!("{}: {}", self.peer_name, message));
+                }
+            }
+
+            _ => { }
+        }
+    }
+
+    // Returns true when the event was meant for the chat and should not be
+    // handled as a game input.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        match event {
+            WindowEvent::Key(Key::Enter, _, Action::Press, _) => {
+                if !self.focused {
+                    self.focused = true;
+                } else if self.input.is_empty() {
+                    self.focused = false;
+                } else {
+                    self.send();
+                }
+
+                return true;
+            }
+
+            WindowEvent::Char(c) if self.focused => {
+                if c.is_ascii() && !c.is_ascii_control() && self.input.len() < MAX_INPUT {
+                    self.input.push(*c);
+                }
+
+                return true;
+            }
+
+            WindowEvent::Key(Key::Backspace, _, Action::Press | Action::Repeat, _) if self.focused => {
+                self.input.pop();
+                return true;
+            }
+
+            WindowEvent::Key(Key::Escape, _, Action::Press, _) if self.focused => {
+                self.focused = false;
+                return true;
+            }
+
+            WindowEvent::Key(..) => {
+                return self.focused;
+            }
+
+            _ => {
+                return false;
+            }
+        }
+    }
+
+    pub fn on_click(&mut self, cursor: (f64, f64)) {
+        self.focused = self.enabled && cursor.0 > 800.0 && cursor.1 > 700.0 && cursor.1 < 780.0;
+    }
+
+    fn send(&mut self) {
+        let message = std::mem::take(&mut self.input);
+
+        if let Some(outbox) = &self.outbox {
+            if outbox.send(&Extension::Chat { message: message.clone() }).is_err() {
+                self.push_message(false, "Chat message could not be sent.");
+                return;
+            }
+        }
+
+        self.push_message(true, &format!("You: {}", message));
+    }
+
+    fn push_message(&mut self, own: bool, message: &str) {
+        // The font only has glyphs for ASCII.
+        let chars: Vec<char> = message.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' }).collect();
+
+        for line in chars.chunks(LINE_LEN) {
+            self.lines.push((own, line.iter().collect()));
+        }
+    }
+
+    pub fn draw_box(&self, shader: &Shader) {
+        if self.enabled {
+            self.input_box.draw(shader);
+        }
+    }
+
+    pub fn draw_text(&self, shader: &Shader, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+        if !self.enabled {
+            return;
+        }
+
+        let start = self.lines.len().saturating_sub(VISIBLE_LINES);
+        for (i, (own, line)) in self.lines[start..].iter().rev().enumerate() {
+            let color = if *own { vec4(0.7, 0.9, 0.7, 1.0) } else { vec4(1.0, 1.0, 1.0, 1.0) };
+            render_text(shader, line.clone(), 810.0, 80.0 + 20.0 * i as f32, 0.35, color, characters, char_quad);
+        }
+
+        if self.focused {
+            let skip = (self.input.len() + 1).saturating_sub(LINE_LEN);
+            render_text(shader, format!("{}_", &self.input[skip..]), 810.0, 48.0, 0.35, vec4(1.0, 1.0, 1.0, 1.0), characters, char_quad);
+        } else {
+            render_text(shader, "Enter: chat".to_string(), 810.0, 48.0, 0.35, vec4(0.6, 0.6, 0.6, 1.0), characters, char_quad);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+// Hosts that want to be found broadcast a beacon on the local network every
+// EVERY. Clients listen for them and list every host heard from recently.
+pub const PORT: u16 = 8385;
+const EVERY: Duration = Duration::from_secs(1);
+
+// A host that has not been heard from for this long is gone.
+const EXPIRE: Duration = Duration::from_secs(4);
+
+// How often a listening browser checks whether it is still wanted.
+const WAKE: Duration = Duration::from_millis(200);
+
+// Told apart from whatever else is sent to the port.
+const APP: &str = "chess";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Beacon {
+    pub app: String,
+    pub name: String,
+    pub port: u16,
+    // The colour the host would like to play, the client still decides.
+    pub host_white: bool
+}
+
+pub struct Listing {
+    pub name: String,
+    pub address: SocketAddr,
+    pub host_white: bool,
+    seen: Instant
+}
+
+// Listens until it is dropped, then the port is given up.
+pub struct Browser {
+    rx: Receiver<(Beacon, SocketAddr)>,
+    stop: Arc<AtomicBool>,
+    pub games: Vec<Listing>
+}
+
+impl Beacon {
+    pub fn new(name: String, port: u16, host_white: bool) -> Beacon {
+        return Beacon { app: APP.to_string(), name: name, port: port, host_white: host_white };
+    }
+}
+
+// What hosts call themselves in the list.
+pub fn host_name() -> String {
+    if let Ok(name) = std::env::var("HOSTNAME") {
+        return name;
+    }
+
+    return std::fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Chess host".to_string());
+}
+
+// Broadcasts the beacon until the returned sender is dropped.
+pub fn announce(beacon: Beacon) -> io::Result<Sender<()>> {
+    return announce_on(beacon, PORT);
+}
+
+fn announce_on(beacon: Beacon, port: u16) -> io::Result<Sender<()>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+
+    let message = serde_json::to_vec(&beacon)?;
+    let (tx, rx) = mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        loop {
+            // Without a network there is no broadcast route, the games on
+            // this machine can still find each other over loopback.
+            if socket.send_to(&message, (Ipv4Addr::BROADCAST, port)).is_err() {
+                let _ = socket.send_to(&message, (Ipv4Addr::new(127, 255, 255, 255), port));
+            }
+
+            match rx.recv_timeout(EVERY) {
+                Err(RecvTimeoutError::Timeout) => { }
+                _ => { break; }
+            }
+        }
+    });
+
+    return Ok(tx);
+}
+
+impl Browser {
+    pub fn start() -> io::Result<Browser> {
+        return Browser::on_port(PORT).map(|(browser, _)| browser);
+    }
+
+    // Port 0 picks a free one, which is returned so others can share it.
+    fn on_port(port: u16) -> io::Result<(Browser, u16)> {
+        // Every client on the machine listens on the same port, which only
+        // works if all of them allow sharing it. Broadcasts reach them all.
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+
+        let socket: UdpSocket = socket.into();
+        socket.set_read_timeout(Some(WAKE))?;
+        let port = socket.local_addr()?.port();
+
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+
+            while !stopped.load(Ordering::SeqCst) {
+                let (length, from) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => { continue; }
+                    Err(_) => { break; }
+                };
+
+                let Ok(beacon) = serde_json::from_slice::<Beacon>(&buffer[..length]) else {
+                    continue;
+                };
+
+                if beacon.app == APP && tx.send((beacon, from)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        return Ok((Browser { rx: rx, stop: stop, games: vec![] }, port));
+    }
+
+    // Takes in the beacons heard since the last call.
+    pub fn update(&mut self) {
+        for (beacon, from) in self.rx.try_iter() {
+            let address = SocketAddr::new(from.ip(), beacon.port);
+
+            match self.games.iter_mut().find(|g| g.address == address) {
+                Some(game) => {
+                    game.name = beacon.name;
+                    game.host_white = beacon.host_white;
+                    game.seen = Instant::now();
+                }
+
+                None => {
+                    self.games.push(Listing { name: beacon.name, address: address, host_white: beacon.host_white, seen: Instant::now() });
+                }
+            }
+        }
+
+        self.games.retain(|g| g.seen.elapsed() < EXPIRE);
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for(browser: &mut Browser, name: &str) -> bool {
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(5) {
+            browser.update();
+            if browser.games.iter().any(|g| g.name == name) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        return false;
+    }
+
+    #[test]
+    fn browsers_list_announced_games() {
+        // Two clients on one machine both get to listen.
+        let (mut first, port) = Browser::on_port(0).unwrap();
+        let (mut second, _) = Browser::on_port(port).unwrap();
+
+        let name = format!("Test host {}", std::process::id());
+        let _announcer = announce_on(Beacon::new(name.clone(), 8384, false), port).unwrap();
+
+        assert!(wait_for(&mut first, &name));
+        assert!(wait_for(&mut second, &name));
+
+        let game = first.games.iter().find(|g| g.name == name).unwrap();
+        assert_eq!(game.address.port(), 8384);
+        assert!(!game.host_white);
+    }
+
+    #[test]
+    fn dropped_browsers_give_up_the_port() {
+        let (browser, port) = Browser::on_port(0).unwrap();
+
+        // Without sharing the port can only be bound once nobody listens.
+        let free = || UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok();
+        assert!(!free());
+
+        drop(browser);
+
+        let start = Instant::now();
+        while !free() {
+            assert!(start.elapsed() < Duration::from_secs(2));
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
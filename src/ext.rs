@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use chess_network_protocol::{Features, Move};
+
+use crate::rules::Board;
+
+// Extensions are advertised by the host as `Features::Other(name)` in its
+// handshake. A client that understands some of them answers with
+// `Extension::Features` and only then may either side send them.
+pub const CHAT: &str = "Chat";
+pub const FRAMED: &str = "Framed";
+pub const HEARTBEAT: &str = "Heartbeat";
+pub const REPLAY: &str = "Replay";
+pub const TAKEBACK: &str = "Takeback";
+pub const REMATCH: &str = "Rematch";
+pub const CHECK: &str = "Check";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Extension {
+    Features(Vec<String>),
+    Chat { message: String },
+    Challenge { nonce: String },
+    Response { digest: String },
+    Ping { id: u64 },
+    Pong { id: u64 },
+    // Announces that the next `states` messages are the game so far, played
+    // from the starting position. Spectators may not move.
+    Replay { states: usize, spectator: bool },
+    // Either side may ask, the other answers. Only the host takes moves
+    // back and tells everyone connected what the game looks like after.
+    TakebackRequest,
+    TakebackAnswer { accepted: bool },
+    TakenBack { plies: usize, board: Board, moves: Vec<Move> },
+    RematchOffer,
+    RematchDeclined,
+    // Starts the next game, in place of the handshake a new connection gets.
+    NewGame { client_white: bool, board: Board, moves: Vec<Move> },
+    // Follows every state, whether the side to move is now in check.
+    Check { in_check: bool }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Incoming<T> {
+    Extension(Extension),
+    Message(T)
+}
+
+pub fn feature(name: &str) -> Features {
+    return Features::Other(name.to_string());
+}
+
+pub fn supports(features: &[Features], name: &str) -> bool {
+    return features.iter().any(|f| *f == feature(name));
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use crate::ext::Extension;
+use crate::net::Outbox;
+
+// Once both sides agreed on the `Heartbeat` extension they ping each other
+// every INTERVAL and answer every ping. A peer that sends nothing at all for
+// `timeout` is gone, not thinking. Without the extension a quiet peer can not
+// be told apart from a slow player, so after the handshake, which has its own
+// timeout, such a peer is only given up on once the connection itself fails.
+// The host then keeps the seat free for it to come back to.
+pub const INTERVAL: Duration = Duration::from_secs(2);
+
+// Silence shorter than this is normal and shows no countdown.
+const GRACE: Duration = Duration::from_secs(6);
+
+// Round trips slower than this count as lag.
+const LAG: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Abandon {
+    // The side that went silent loses.
+    Award,
+    // The game ends without a result.
+    Adjourn
+}
+
+pub struct Heartbeat {
+    pub enabled: bool,
+    pub timeout: Duration,
+    pub rule: Abandon,
+    pub rtt: Option<Duration>,
+    last_heard: Instant,
+    last_sent: Instant,
+    // The newest ping that has not been answered yet.
+    pending: Option<(u64, Instant)>,
+    next_id: u64
+}
+
+impl Abandon {
+    pub fn from_name(name: &str) -> Option<Abandon> {
+        return match name {
+            "award" => Some(Abandon::Award),
+            "adjourn" => Some(Abandon::Adjourn),
+            _ => None
+        };
+    }
+}
+
+impl Heartbeat {
+    pub fn new(timeout: Duration, rule: Abandon) -> Heartbeat {
+        return Heartbeat {
+            enabled: false,
+            timeout: timeout,
+            rule: rule,
+            rtt: None,
+            last_heard: Instant::now(),
+            last_sent: Instant::now(),
+            pending: None,
+            next_id: 0
+        };
+    }
+
+    // Anything at all from the peer shows it is still there.
+    pub fn heard(&mut self) {
+        self.last_heard = Instant::now();
+    }
+
+    // Called every frame, sends a ping when one is due.
+    pub fn tick(&mut self, outbox: &Outbox) {
+        if !self.enabled || self.last_sent.elapsed() < INTERVAL {
+            return;
+        }
+
+        let _ = outbox.send(&Extension::Ping { id: self.next_id });
+        self.pending = Some((self.next_id, Instant::now()));
+        self.next_id += 1;
+        self.last_sent = Instant::now();
+    }
+
+    pub fn on_extension(&mut self, extension: &Extension, outbox: &Outbox) {
+        match extension {
+            Extension::Ping { id } => {
+                let _ = outbox.send(&Extension::Pong { id: *id });
+            }
+
+            Extension::Pong { id } => {
+                if let Some((pending, sent)) = self.pending {
+                    if pending == *id {
+                        self.rtt = Some(sent.elapsed());
+                        self.pending = None;
+                    }
+                }
+            }
+
+            _ => { }
+        }
+    }
+
+    // Either the last round trip was slow or the current one already is.
+    pub fn lagging(&self) -> bool {
+        let slow = self.rtt.map(|rtt| rtt >= LAG).unwrap_or(false);
+        let waiting = self.pending.map(|(_, sent)| sent.elapsed() >= LAG).unwrap_or(false);
+
+        return slow || waiting || self.countdown().is_some();
+    }
+
+    // Whole seconds left before the peer is given up on, once it has been
+    // quiet for longer than usual.
+    pub fn countdown(&self) -> Option<u64> {
+        let silence = self.last_heard.elapsed();
+
+        if !self.enabled || silence < GRACE.min(self.timeout / 2) {
+            return None;
+        }
+
+        return Some(self.timeout.saturating_sub(silence).as_secs());
+    }
+
+    pub fn expired(&self) -> bool {
+        return self.enabled && self.last_heard.elapsed() >= self.timeout;
+    }
+}
@@ -1,34 +1,64 @@
 mod shader;
 mod model;
+mod rules;
+mod audio;
+mod ext;
+mod chat;
+mod board;
+mod backend;
+mod ai;
+mod bot;
+mod bridge;
+mod wire;
+mod secure;
+mod uci;
+mod net;
+mod heartbeat;
+mod menu;
+mod discovery;
+mod record;
+mod replay;
+mod analysis;
+mod takeback;
+mod rematch;
 
 use std::collections::HashMap;
 use std::{env, thread};
 use std::io::Read;
 use std::net::TcpListener;
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc};
+use std::sync::mpsc::{Receiver, Sender};
 
 use model::*;
 use shader::*;
+use audio::*;
+use chat::Chat;
+use ext::Extension;
+use backend::ChessBackend;
+use rules::Position;
+use board::BoardState;
+use rules::Board;
+use uci::UciEngine;
+use net::{Outbox, Status};
+use heartbeat::{Abandon, Heartbeat};
+use record::Recorder;
+use analysis::Analysis;
+use takeback::Takeback;
+use rematch::Rematch;
 
 use glfw::*;
 use glm::*;
 use freetype::Library;
 use freetype::face::LoadFlag;
 use chess_network_protocol::*;
-use serde::Deserialize;
+use serde::Serialize;
 
 #[derive(PartialEq)]
 enum HostState {
     WaitingForCon,
     HostPlaying,
-    ClientPlaying,
-    TestingClientMove,
-    BadClientMove,
-    RespondToClient,
-    SendToClient,
-    PromotingHost,
-    PromotingClient
+    ClientPlaying
 }
 
 #[derive(PartialEq)]
@@ -39,18 +69,55 @@ enum ClientState {
     WaitingForResponse,
 }
 
+type HostEvent = net::Event<ClientToServerHandshake, ClientToServer>;
+type ClientEvent = net::Event<ServerToClientHandshake, ServerToClient>;
+
 struct HostGame {
-    chess: ludviggl_chess::Game,
+    chess: Box<dyn ChessBackend>,
     board: [Model2D; 64],
     white_pieces: [Model2D; 6],
     black_pieces: [Model2D; 6],
     game_end: bool,
     promoting: bool,
-    selected_prom: ludviggl_chess::Piece,
+    selected_prom: Piece,
     host_turn: bool,
-    recieved_move: (usize, usize, usize, usize),
+    host_white: bool,
+    joever: Joever,
     made_move: (usize, usize, usize, usize),
     client_connected: bool,
+    opponent: &'static str,
+    outbox: Option<Outbox>,
+    status: Status,
+    peer: Option<String>,
+    heartbeat: Heartbeat,
+    // The client went silent and the game was ended for it.
+    timed_out: bool,
+    // The computer had no move to make and the game was ended for it.
+    computer_failed: bool,
+    // Stops announcing the game on the network once dropped.
+    announcer: Option<Sender<()>>,
+    // The computer, when it is the opponent.
+    computer: Option<Computer>,
+    // The connection playing the other side, connections are numbered in the
+    // order they arrive.
+    player: Option<usize>,
+    spectators: Vec<Spectator>,
+    // The extensions the player agreed on.
+    accepted: Vec<String>,
+    // Every state sent so far, replayed to whoever joins late.
+    log: Vec<ServerToClient>,
+    // Every move played, from either side.
+    history: Vec<Move>,
+    analysis: Option<Analysis>,
+    takeback: Takeback,
+    rematch: Rematch,
+    // The side to move is in check.
+    in_check: bool,
+    hot_seat: bool,
+    flip: bool,
+    flipped: bool,
+    sounds: Vec<Sound>,
+    chat: Chat,
     state: HostState
 }
 
@@ -58,18 +125,56 @@ struct ClientGame {
     board: [Model2D; 64],
     white_pieces: [Model2D; 6],
     black_pieces: [Model2D; 6],
-    white_pos: Vec<(usize, usize, usize)>,
-    black_pos: Vec<(usize, usize, usize)>,
+    pieces: BoardState,
+    // Our own model of the game, used to check the boards the server sends.
+    position: Position,
+    desync: Option<String>,
     game_end: bool,
     promoting: bool,
-    _selected_prom: chess_network_protocol::Piece,
+    selected_prom: chess_network_protocol::Piece,
     host_turn: bool,
+    client_white: bool,
+    features: Vec<Features>,
+    moves: Vec<Move>,
+    // Every move of the game so far, ours and the host's.
+    played: Vec<Move>,
+    // States still to come before the replay of the game so far is done.
+    replaying: usize,
+    spectating: bool,
+    analysis: Option<Analysis>,
+    takeback: Takeback,
+    rematch: Rematch,
+    in_check: bool,
+    joever: Joever,
     m_from: (usize, usize),
     m_to: (usize, usize),
-    send_move: bool,
+    outbox: Option<Outbox>,
+    status: Status,
+    peer: Option<String>,
+    // When our last move went out, to time the host's answer.
+    move_sent: Option<std::time::Instant>,
+    heartbeat: Heartbeat,
+    timed_out: bool,
+    sounds: Vec<Sound>,
+    chat: Chat,
     state: ClientState
 }
 
+// Every position the computer is asked about is numbered, and only the
+// answer to the latest one is played. A takeback or a new game may have
+// changed the position while it was thinking.
+struct Computer {
+    tx: Sender<(u64, Position, Vec<Move>)>,
+    answers: Receiver<(u64, Option<Move>)>,
+    asked: u64
+}
+
+struct Spectator {
+    id: usize,
+    outbox: Outbox,
+    accepted: Vec<String>
+}
+
 struct Character {
     texture_id: u32,
     size: IVec2,
@@ -78,7 +183,7 @@ struct Character {
 }
 
 impl HostGame {
-    pub fn new() -> HostGame {
+    pub fn new(chess: Box<dyn ChessBackend>) -> HostGame {
         let mut b: [Model2D; 64] = (0..64).map(|_| Model2D::dummy()).collect::<Vec<_>>().try_into().unwrap();
         let wp: [Model2D; 6] = (0..6i8).map(|i| Model2D::white_piece(i)).collect::<Vec<_>>().try_into().unwrap();
         let bp: [Model2D; 6] = (0..6i8).map(|i| Model2D::black_piece(i)).collect::<Vec<_>>().try_into().unwrap();
@@ -105,20 +210,76 @@ impl HostGame {
         }
     
         return HostGame{ 
-            chess: ludviggl_chess::Game::new(), 
+            chess: chess, 
             board: b,
             white_pieces: wp,
             black_pieces: bp,
             game_end: false,
             promoting: false,
-            selected_prom: ludviggl_chess::Piece::Pawn,
+            selected_prom: Piece::None,
             host_turn: true,
-            recieved_move: (usize::MAX, usize::MAX, usize::MAX, usize::MAX),
+            host_white: true,
+            joever: Joever::Ongoing,
             made_move: (usize::MAX, usize::MAX, usize::MAX, usize::MAX),
             client_connected: false,
+            opponent: "Client",
+            outbox: None,
+            status: Status::Connecting,
+            peer: None,
+            heartbeat: Heartbeat::new(std::time::Duration::from_secs(30), Abandon::Award),
+            timed_out: false,
+            computer_failed: false,
+            announcer: None,
+            computer: None,
+            player: None,
+            spectators: vec![],
+            accepted: vec![],
+            log: vec![],
+            history: vec![],
+            analysis: None,
+            takeback: Takeback::new(),
+            rematch: Rematch::new(),
+            in_check: false,
+            hot_seat: false,
+            flip: false,
+            flipped: false,
+            sounds: vec![],
+            chat: Chat::new("Client"),
             state: HostState::WaitingForCon
         };
     }
+
+    // A closed connection shows up as an event of its own, so a failed send
+    // needs no handling here.
+    fn send<T: Serialize>(&self, message: &T) {
+        if let Some(outbox) = &self.outbox {
+            let _ = outbox.send(message);
+        }
+    }
+
+    // Sends to the player and everyone watching.
+    fn broadcast<T: Serialize>(&self, message: &T) {
+        self.send(message);
+
+        for spectator in &self.spectators {
+            let _ = spectator.outbox.send(message);
+        }
+    }
+
+    // Like broadcast, but only to those who agreed on the extension.
+    fn broadcast_extension(&self, name: &str, message: &Extension) {
+        if self.accepted.iter().any(|f| f == name) {
+            self.send(message);
+        }
+
+        for spectator in self.spectators.iter().filter(|s| s.accepted.iter().any(|f| f == name)) {
+            let _ = spectator.outbox.send(message);
+        }
+    }
+
+    fn spectator(&self, id: usize) -> Option<&Outbox> {
+        return self.spectators.iter().find(|s| s.id == id).map(|s| &s.outbox);
+    }
 }
 
 impl ClientGame {
@@ -152,25 +313,45 @@ impl ClientGame {
             board: b,
             white_pieces: wp,
             black_pieces: bp,
-            black_pos: vec![],
-            white_pos: vec![],
+            pieces: BoardState::new(),
+            position: Position::new(),
+            desync: None,
             game_end: false,
             promoting: false,
-            _selected_prom: chess_network_protocol::Piece::None,
+            selected_prom: chess_network_protocol::Piece::None,
             host_turn: true,
+            client_white: true,
+            features: vec![],
+            moves: vec![],
+            played: vec![],
+            replaying: 0,
+            spectating: false,
+            analysis: None,
+            takeback: Takeback::new(),
+            rematch: Rematch::new(),
+            in_check: false,
+            joever: Joever::Ongoing,
             m_from: (usize::MAX, usize::MAX),
             m_to: (usize::MAX, usize::MAX),
-            send_move: false,
+            outbox: None,
+            status: Status::Connecting,
+            peer: None,
+            move_sent: None,
+            heartbeat: Heartbeat::new(std::time::Duration::from_secs(30), Abandon::Award),
+            timed_out: false,
+            sounds: vec![],
+            chat: Chat::new("Host"),
             state: ClientState::Pending
         };
     }
 }
 
 fn main() {
-    let host: bool;
-    let address: String;
-    let c_white: bool;
-    let args: Vec<String> = env::args().collect::<Vec<String>>();
+    let mut host: bool;
+    let mut address: String;
+    let mut c_white: bool;
+    let args: Vec<String> = env::args().filter(|a| !a.starts_with("--")).collect::<Vec<String>>();
+    let flags: Vec<String> = env::args().filter(|a| a.starts_with("--")).collect::<Vec<String>>();
     if args.len() > 1 {
         host = false;
         address = args[1].clone();
@@ -185,6 +366,74 @@ fn main() {
         c_white = true;
     }
 
+    if let Some(path) = flag_value(&flags, "replay") {
+        let engine = flag_value(&flags, "engine").unwrap_or("ludviggl".to_string());
+        let problems = replay::run(&path, &engine).unwrap_or_else(|e| panic!("Could not replay \"{}\": {}", path, e));
+        std::process::exit(if problems == 0 { 0 } else { 1 });
+    }
+
+    // Length-prefixed framing is offered and accepted unless turned off.
+    let framing = !has_flag(&flags, "no-framing");
+    // The host takes a WebSocket connection instead, for browser clients.
+    let websocket = has_flag(&flags, "websocket");
+    let password = flag_value(&flags, "password");
+    let tls = has_flag(&flags, "tls");
+    // Hosts on the LAN listen on every interface and announce themselves.
+    let mut lan = has_flag(&flags, "lan");
+    let mut host_white = flag_value(&flags, "color").map(|c| c.to_lowercase() != "b").unwrap_or(true);
+
+    if tls && websocket {
+        panic!("--tls can not be combined with --websocket.");
+    }
+
+    // Every message sent and received goes to this file, for debugging.
+    let recorder = flag_value(&flags, "record").map(|path| {
+        Recorder::create(&path).unwrap_or_else(|e| panic!("Could not create \"{}\": {}", path, e))
+    });
+
+    let tls_client = if tls { Some(secure::client_config(flag_value(&flags, "tls-fingerprint"), has_flag(&flags, "tls-insecure"))) } else { None };
+
+    if let Some(name) = flag_value(&flags, "bot") {
+        if host {
+            panic!("The bot plays as a client, pass the host address.");
+        }
+
+        let strategy = if name == "uci" {
+            uci_strategy(&flags)
+        } else {
+            bot::Strategy::from_name(&name, search_config(&flags))
+                .unwrap_or_else(|| panic!("Unknown bot strategy \"{}\", expected random, greedy, search or uci.", name))
+        };
+        let (connection, des) = bot::connect(&address, c_white, &tls_client, &password, framing, &recorder, heartbeat_config(&flags))
+            .unwrap_or_else(|e| panic!("Could not connect to {}: {}", address, e));
+        bot::run(connection, des, c_white, strategy).unwrap();
+        return;
+    }
+
+    if let Some(name) = flag_value(&flags, "bridge") {
+        if host {
+            panic!("The bridge plays as a client, pass the host address.");
+        }
+
+        let dialect = bridge::Dialect::from_name(&name)
+            .unwrap_or_else(|| panic!("Unknown bridge protocol \"{}\", expected uci or xboard.", name));
+        let (connection, des) = bot::connect(&address, c_white, &tls_client, &password, framing, &recorder, heartbeat_config(&flags))
+            .unwrap_or_else(|e| panic!("Could not connect to {}: {}", address, e));
+        bridge::run(connection, des, c_white, dialect).unwrap();
+        return;
+    }
+
+    // Without an address or a mode on the command line the player picks one
+    // on the start screen.
+    let show_menu = host && !["host", "local", "ai"].iter().any(|f| has_flag(&flags, f));
+
+    let mut audio_config = AudioConfig::new();
+    audio_config.enabled = !has_flag(&flags, "no-sound");
+    audio_config.muted = has_flag(&flags, "mute");
+    if let Some(v) = flag_value(&flags, "volume").and_then(|v| v.parse::<f32>().ok()) {
+        audio_config.volume = v.clamp(0.0, 1.0);
+    }
+
     let mut glfw = init(fail_on_errors!()).unwrap();
 
     glfw.window_hint(WindowHint::Resizable(false));
@@ -200,6 +449,7 @@ fn main() {
     
     window.make_current();
     window.set_key_polling(true);
+    window.set_char_polling(true);
     window.set_cursor_pos_polling(true);
     window.set_mouse_button_polling(true);
     gl::load_with(|s| window.get_proc_address(s));
@@ -219,6 +469,7 @@ fn main() {
     let text_shader = Shader::new("shaders/text.vert", "shaders/text.frag");
 
     let sprites = load_texture("assets/sprites.png");
+    let mut audio = Audio::new(&audio_config);
 
     unsafe {
     gl::Viewport(0, 0, 1000, 800);
@@ -226,175 +477,108 @@ fn main() {
     gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
     }
 
-    // We are connecting.
-    if !host {
-        let game_ = Arc::new(Mutex::new(ClientGame::new()));
-        let render = Arc::clone(&game_);
-        let network = Arc::clone(&game_);
+    // A connection made from the start screen, with the event that
+    // announced it.
+    let mut connected: Option<(Receiver<ClientEvent>, ClientEvent)> = None;
 
-        thread::spawn(move || {
-            let stream = TcpStream::connect(address).unwrap();
-            let mut de = serde_json::Deserializer::from_reader(&stream);
+    if show_menu {
+        let mut menu = menu::Menu::new(address.clone(), c_white, lan);
+        let mut attempt: Option<Receiver<ClientEvent>> = None;
+        host = false;
 
-            let handshake = ClientToServerHandshake {
-                server_color: if c_white { Color::Black } else { Color::White }
-            };
-        
-            serde_json::to_writer(&stream, &handshake).unwrap();
+        while !window.should_close() && connected.is_none() && !host {
+            if let Some(rx) = &attempt {
+                match rx.try_recv() {
+                    Ok(net::Event::Closed(e)) => {
+                        menu.error = Some(format!("Could not connect: {}", e).chars().take(60).collect());
+                        menu.connecting = false;
+                        attempt = None;
+                    }
 
-            let des = ServerToClientHandshake::deserialize(&mut de).unwrap();
+                    Ok(event) => { connected = attempt.take().map(|rx| (rx, event)); }
 
-            let mut game = network.lock().unwrap();
+                    Err(_) => { }
+                }
+            }
 
-            game.state = if c_white { ClientState::ClientPlaying } else { ClientState::HostPlaying };
-            game.host_turn = if c_white { false } else { true };
+            menu.update();
 
-            for y in 0..8 {
-                for x in 0..8 {
-                    if (des.board[y][x] as i8) < 6 {
-                        if des.board[y][x] as i8 == 1 { game.black_pos.push((x, y, 2)); }
-                        else if des.board[y][x] as i8 == 2 { game.black_pos.push((x, y, 3)); }
-                        else if des.board[y][x] as i8 == 3 { game.black_pos.push((x, y, 1)); }
-                        else { game.black_pos.push((x, y, des.board[y][x] as usize)); }
-                    }
+            unsafe {
+                gl::ClearColor(0.3, 0.3, 0.2, 1.0);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
 
-                    if (des.board[y][x] as i8) < 12 && des.board[y][x] as i8 > 5 {
-                        if des.board[y][x] as i8 == 7 { game.white_pos.push((x, y, 2)); }
-                        else if des.board[y][x] as i8 == 8 { game.white_pos.push((x, y, 3)); }
-                        else if des.board[y][x] as i8 == 9 { game.white_pos.push((x, y, 1)); }
-                        else { game.white_pos.push((x, y, des.board[y][x] as usize - 6)); }
-                    }
-                }
+                tile_shader.use_program();
+                tile_shader.set_mat4("projection", proj);
+                menu.draw_boxes(&tile_shader);
+
+                text_shader.use_program();
+                text_shader.set_mat4("projection", text_proj);
+                menu.draw_text(&text_shader, &characters, &mut char_quad);
             }
 
-            std::mem::drop(game);
+            for (_, event) in flush_messages(&events) {
+                if let WindowEvent::Key(Key::Escape, _, Action::Press, _) = event {
+                    window.set_should_close(true);
+                }
 
-            loop {
-                let mut game = network.lock().unwrap();
-                
-                match game.state {
-                    ClientState::ClientPlaying => {
-                        if game.send_move {
-                            let m = Move{ start_x: game.m_from.0, start_y: game.m_from.1, end_x: game.m_to.0, end_y: game.m_to.1, promotion: Piece::None };
-                            let move_to_send = ClientToServer::Move(m);
-                            serde_json::to_writer(&stream, &move_to_send).unwrap();
-                            game.send_move = false;
-                            game.m_from = (usize::MAX, usize::MAX);
-                            game.m_to = (usize::MAX, usize::MAX);
-                            game.state = ClientState::WaitingForResponse;
-                        }
+                match menu.handle_event(&event, window.get_cursor_pos()) {
+                    Some(menu::Choice::Host(l, white)) => {
+                        host = true;
+                        lan = l;
+                        host_white = white;
                     }
 
-                    ClientState::WaitingForResponse => {
-                        std::mem::drop(game);
-                        let res = ServerToClient::deserialize(&mut de).unwrap();
-
-                        game = network.lock().unwrap();
-
-                        match res {
-                            ServerToClient::State { board: b, moves: _, joever: j, move_made: _ } => {
-                                game.black_pos.clear();
-                                game.white_pos.clear();
-                                
-                                for y in 0..8 {
-                                    for x in 0..8 {
-                                        if (b[y][x] as i8) < 6 {
-                                            if b[y][x] as i8 == 1 { game.black_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 2 { game.black_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 3 { game.black_pos.push((x, y, 1)); }
-                                            else { game.black_pos.push((x, y, b[y][x] as usize)); }
-                                        }
-                    
-                                        if (b[y][x] as i8) < 12 && b[y][x] as i8 > 5 {
-                                            if b[y][x] as i8 == 7 { game.white_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 8 { game.white_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 9 { game.white_pos.push((x, y, 1)); }
-                                            else { game.white_pos.push((x, y, b[y][x] as usize - 6)); }
-                                        }
-                                    }
-                                }
-                                
-                                game.game_end = if j != Joever::Ongoing { true } else { false };
-                                game.host_turn = true;
+                    Some(menu::Choice::Join(a, white)) => {
+                        attempt = Some(spawn_connect(&a, white, &tls_client, &password, framing, &recorder));
+                        address = a;
+                        c_white = white;
+                    }
 
-                                game.state = ClientState::HostPlaying;
-                            }
+                    None => { }
+                }
+            }
 
-                            ServerToClient::Error { board: b, moves: _, joever: _, message: _ } => {
-                                for y in 0..8 {
-                                    for x in 0..8 {
-                                        if (b[y][x] as i8) < 6 {
-                                            if b[y][x] as i8 == 1 { game.black_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 2 { game.black_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 3 { game.black_pos.push((x, y, 1)); }
-                                            else { game.black_pos.push((x, y, b[y][x] as usize)); }
-                                        }
-                    
-                                        if (b[y][x] as i8) < 12 && b[y][x] as i8 > 5 {
-                                            if b[y][x] as i8 == 7 { game.white_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 8 { game.white_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 9 { game.white_pos.push((x, y, 1)); }
-                                            else { game.white_pos.push((x, y, b[y][x] as usize - 6)); }
-                                        }
-                                    }
-                                }
+            window.swap_buffers();
+            glfw.poll_events();
 
-                                game.state = ClientState::ClientPlaying;
-                            }
+            std::thread::sleep(std::time::Duration::from_millis(7));
+        }
 
-                            _ => { }
-                        }
-                    }
+        if window.should_close() {
+            return;
+        }
 
-                    ClientState::HostPlaying => {
-                        std::mem::drop(game);
-                        let res = ServerToClient::deserialize(&mut de).unwrap();
-
-                        game = network.lock().unwrap();
-
-                        match res {
-                            ServerToClient::State { board: b, moves: _, joever: j, move_made: _ } => {
-                                game.black_pos.clear();
-                                game.white_pos.clear();
-                                
-                                for y in 0..8 {
-                                    for x in 0..8 {
-                                        if (b[y][x] as i8) < 6 {
-                                            if b[y][x] as i8 == 1 { game.black_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 2 { game.black_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 3 { game.black_pos.push((x, y, 1)); }
-                                            else { game.black_pos.push((x, y, b[y][x] as usize)); }
-                                        }
-                    
-                                        if (b[y][x] as i8) < 12 && b[y][x] as i8 > 5 {
-                                            if b[y][x] as i8 == 7 { game.white_pos.push((x, y, 2)); }
-                                            else if b[y][x] as i8 == 8 { game.white_pos.push((x, y, 3)); }
-                                            else if b[y][x] as i8 == 9 { game.white_pos.push((x, y, 1)); }
-                                            else { game.white_pos.push((x, y, b[y][x] as usize - 6)); }
-                                        }
-                                    }
-                                }
-                                
-                                game.game_end = if j != Joever::Ongoing { true } else { false };
-                                game.host_turn = false;
+        host = connected.is_none();
+    }
 
-                                game.state = ClientState::ClientPlaying;
-                            }
+    // We are connecting.
+    if !host {
+        let mut game = ClientGame::new();
+        game.client_white = c_white;
+        game.peer = Some(address.clone());
+        game.heartbeat = heartbeat_config(&flags);
+
+        let rx = match connected {
+            Some((rx, event)) => {
+                client_on_event(&mut game, event);
+                rx
+            }
 
-                            _ => { }
-                        }
-                    }
+            None => spawn_connect(&address, c_white, &tls_client, &password, framing, &recorder)
+        };
 
-                    _ => { }
-                }
-                
-                std::mem::drop(game);
-                std::thread::sleep(std::time::Duration::from_millis(7));
+        while !window.should_close() {
+            for event in rx.try_iter() {
+                client_on_event(&mut game, event);
             }
-        });
 
-        while !window.should_close() {
-            let mut game = render.lock().unwrap();
+            if let Some(outbox) = &game.outbox {
+                game.heartbeat.tick(outbox);
+            }
+
+            if !game.game_end && game.heartbeat.expired() {
+                client_abandon(&mut game);
+            }
 
             unsafe {
                 gl::ClearColor(0.3, 0.3, 0.2, 1.0);
@@ -405,306 +589,252 @@ fn main() {
                 for i in 0..64 {
                     game.board[i].draw(&tile_shader);
                 }
+                game.chat.draw_box(&tile_shader);
 
                 piece_shader.use_program();
                 piece_shader.set_mat4("projection", proj);
                 gl::BindTexture(gl::TEXTURE_2D, sprites);
-                for (x, y, piece) in game.white_pos.to_owned() {
-                    game.white_pieces[piece as usize].transform.translation.x = x as f32 - 3.5;
-                    game.white_pieces[piece as usize].transform.translation.y = -1.0 * (-(y as f32) + 3.5);
-                    game.white_pieces[piece as usize].draw(&piece_shader);
-                }
-
-                for (x, y, piece) in game.black_pos.to_owned() {
-                    game.black_pieces[piece as usize].transform.translation.x = x as f32 - 3.5;
-                    game.black_pieces[piece as usize].transform.translation.y = -1.0 * (-(y as f32) + 3.5);
-                    game.black_pieces[piece as usize].draw(&piece_shader);
+                match &game.analysis {
+                    Some(analysis) => draw_pieces(&BoardState::from_protocol(analysis.board()), &mut game.white_pieces, &mut game.black_pieces, &piece_shader, false),
+                    None => draw_pieces(&game.pieces, &mut game.white_pieces, &mut game.black_pieces, &piece_shader, false)
                 }
                 gl::BindTexture(gl::TEXTURE_2D, 0);
 
                 text_shader.use_program();
                 text_shader.set_mat4("projection", text_proj);
                 if game.game_end {
-                    let winner = if game.host_turn { "You lose!" } else { "You win!" };
+                    let stalemate = game.features.contains(&Features::Stalemate);
+                    let winner = if game.timed_out && game.joever == Joever::Indeterminate {
+                        "Adjourned."
+                    } else {
+                        result_text(&game.joever, game.client_white, stalemate).unwrap_or(if game.host_turn { "You lose!" } else { "You win!" })
+                    };
                     render_text(&text_shader, winner.to_string(), 800.0, 640.0, 0.7, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
                     render_text(&text_shader, "Press \'ESC\' to exit.".to_string(), 800.0, 610.0, 0.44, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    if game.timed_out {
+                        render_text(&text_shader, "The host timed out.".to_string(), 800.0, 580.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+                    render_analysis(&text_shader, &game.analysis, &characters, &mut char_quad);
+
+                    if !game.spectating && game.outbox.is_some() {
+                        render_rematch(&text_shader, &game.rematch, "Host", &characters, &mut char_quad);
+                    }
+                } else if game.promoting {
+                    render_text(&text_shader, "Select promotion:".to_string(), 800.0, 640.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "1: Rook".to_string(), 800.0, 610.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "2: Knight".to_string(), 800.0, 580.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "3: Bishop".to_string(), 800.0, 550.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "4: Queen".to_string(), 800.0, 520.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
                 } else {
-                    let turn = if game.host_turn { "Host is playing." } else { "You are playing." };
+                    let turn = if game.spectating { "Watching the game." } else if game.host_turn { "Host is playing." } else { "You are playing." };
                     render_text(&text_shader, turn.to_string(),  800.0, 640.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+
+                    if game.in_check {
+                        render_text(&text_shader, "Check!".to_string(), 800.0, 565.0, 0.45, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+
+                    if !game.spectating {
+                        render_takeback(&text_shader, &game.takeback, client_has_moved(&game), "Host", &characters, &mut char_quad);
+                    }
+
+                    if let Some(left) = game.heartbeat.countdown() {
+                        render_text(&text_shader, "The host is silent.".to_string(), 800.0, 610.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                        render_text(&text_shader, format!("Giving up in {}s.", left), 800.0, 590.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+                }
+
+                if let Some(m) = game.played.last() {
+                    let last = format!("Move {}: {}", game.played.len(), rules::move_name(m));
+                    render_text(&text_shader, last, 810.0, 670.0, 0.35, vec4(0.8, 0.8, 0.8, 1.0), &characters, &mut char_quad);
+                }
+
+                if let Some(desync) = &game.desync {
+                    render_text(&text_shader, "Board out of sync!".to_string(), 810.0, 770.0, 0.4, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    // The status takes the space under the title, the details
+                    // go in the free space above the chat.
+                    for (i, line) in wrap_text(desync, 26).into_iter().enumerate() {
+                        render_text(&text_shader, line, 810.0, 415.0 - 16.0 * i as f32, 0.3, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+                }
+
+                render_status(&text_shader, game.status, &game.heartbeat, &game.peer, &characters, &mut char_quad);
+
+                game.chat.draw_text(&text_shader, &characters, &mut char_quad);
+
+                if audio.available() {
+                    let sound = if audio.muted { "M: Unmute sound" } else { "M: Mute sound" };
+                    render_text(&text_shader, sound.to_string(), 810.0, 20.0, 0.35, vec4(0.8, 0.8, 0.8, 1.0), &characters, &mut char_quad);
                 }
             }
 
-            // match game.chess.get_state() {
-            //     ludviggl_chess::State::CheckMate => {
-            //         game.game_end = true;
-            //     }
-
-            //     ludviggl_chess::State::SelectPromotion => {
-            //         game.promoting = true;
-            //         text_shader.use_program();
-            //         text_shader.set_mat4("projection", text_proj);
-            //         render_text(&text_shader, "Select promotion:".to_string(), 800.0, 600.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-            //         render_text(&text_shader, "1: Rook".to_string(), 800.0, 570.0, 0.6, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-            //         render_text(&text_shader, "2: Knight".to_string(), 800.0, 540.0, 0.6, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-            //         render_text(&text_shader, "3: Bishop".to_string(), 800.0, 510.0, 0.6, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-            //         render_text(&text_shader, "4: Queen".to_string(), 800.0, 480.0, 0.6, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-
-            //         let prom = game.selected_prom;
-            //         if game.chess.select_promotion(prom).is_ok() {
-            //             game.promoting = false;
-            //             game.selected_prom = ludviggl_chess::Piece::Pawn;
-            //         }
-            //     }
-
-            //     _ => { }
-            // }
+            for sound in game.sounds.drain(..) {
+                audio.play(sound);
+            }
 
             for (_, event) in flush_messages(&events) {
+                if game.chat.handle_event(&event) {
+                    continue;
+                }
+
                 match event {
                     WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
                         window.set_should_close(true);
                     }
 
+                    WindowEvent::Key(Key::M, _, Action::Press, _) => {
+                        audio.toggle_mute();
+                    }
+
                     WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                        game.chat.on_click(window.get_cursor_pos());
+
                         if !game.game_end && !game.promoting && game.state == ClientState::ClientPlaying {
                             client_on_pick(&mut game, &window);
                         }
+
+                        if game.game_end {
+                            if let Some((x, y)) = square_at(window.get_cursor_pos(), false) {
+                                let analysis = start_analysis(&mut game.analysis, &game.played);
+                                analysis.pick(x, y);
+                                analysis.colour(&mut game.board);
+                            }
+                        }
+                    }
+
+                    WindowEvent::Key(key @ (Key::T | Key::Y | Key::N | Key::R), _, Action::Press, _) => {
+                        client_on_key(&mut game, key);
+                    }
+
+                    WindowEvent::Key(key @ (Key::Left | Key::Right | Key::Home | Key::End), _, Action::Press | Action::Repeat, _) if game.game_end => {
+                        let analysis = start_analysis(&mut game.analysis, &game.played);
+                        analysis_key(analysis, key);
+                        analysis.colour(&mut game.board);
                     }
 
                     _ => {}
                 }
 
-                // if game.promoting && game.host_turn {
-                //     match event {
-                //         WindowEvent::Key(Key::Num1, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Rook; }
-                //         WindowEvent::Key(Key::Num2, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Knight; }
-                //         WindowEvent::Key(Key::Num3, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Bishop; }
-                //         WindowEvent::Key(Key::Num4, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Queen; }
-
-                //         _ => { }
-                //     }
-                // }
+                if game.promoting {
+                    let white = game.client_white;
+                    let prom = match event {
+                        WindowEvent::Key(Key::Num1, _, Action::Press, _) => { if white { Piece::WhiteRook } else { Piece::BlackRook } }
+                        WindowEvent::Key(Key::Num2, _, Action::Press, _) => { if white { Piece::WhiteKnight } else { Piece::BlackKnight } }
+                        WindowEvent::Key(Key::Num3, _, Action::Press, _) => { if white { Piece::WhiteBishop } else { Piece::BlackBishop } }
+                        WindowEvent::Key(Key::Num4, _, Action::Press, _) => { if white { Piece::WhiteQueen } else { Piece::BlackQueen } }
+
+                        _ => { Piece::None }
+                    };
+
+                    if prom != Piece::None {
+                        game.selected_prom = prom;
+                        game.promoting = false;
+                        client_send_move(&mut game);
+                    }
+                }
             }
 
             window.swap_buffers();
             glfw.poll_events();
 
-            std::mem::drop(game);
             std::thread::sleep(std::time::Duration::from_millis(7));
         }
 
     } else {
 
         // We are hosting.
-        let game_ = Arc::new(Mutex::new(HostGame::new()));
-        let render = Arc::clone(&game_);
-        let network = Arc::clone(&game_);
-        
-        thread::spawn(move || {
-            let listener = TcpListener::bind("127.0.0.1:8384").unwrap();
-            let (stream, _addr) = listener.accept().unwrap();
-            
-            let mut de = serde_json::Deserializer::from_reader(&stream);
-            let des = ClientToServerHandshake::deserialize(&mut de).unwrap();
-            
-            let mut game = network.lock().unwrap();
-
-            game.host_turn = if des.server_color == Color::White { true } else { false };
-            game.client_connected = true;
-            game.state = if game.host_turn { HostState::HostPlaying } else { HostState::ClientPlaying };
+        let tls_server = if tls {
+            let (config, fingerprint) = secure::server_config().unwrap();
+            println!("TLS certificate fingerprint: {}", fingerprint);
+            Some(config)
+        } else {
+            None
+        };
 
-            let mut board_copy = [[Piece::None; 8]; 8];
-            for (p, x, y) in game.chess.get_black_positions().to_owned() {
-                board_copy[y as usize][x as usize] = match p {
-                    ludviggl_chess::Piece::Pawn => { Piece::BlackPawn }
-                    ludviggl_chess::Piece::Rook => { Piece::BlackRook }
-                    ludviggl_chess::Piece::Knight => { Piece::BlackKnight }
-                    ludviggl_chess::Piece::Bishop => { Piece::BlackBishop }
-                    ludviggl_chess::Piece::King => { Piece::BlackKing }
-                    ludviggl_chess::Piece::Queen => { Piece::BlackQueen }
-                }
-            }
+        let engine = flag_value(&flags, "engine").unwrap_or("ludviggl".to_string());
+        let chess = backend::from_name(&engine).unwrap_or_else(|| panic!("Unknown engine \"{}\", expected ludviggl or builtin.", engine));
+        let mut game = HostGame::new(chess);
+        game.heartbeat = heartbeat_config(&flags);
+
+        // The rules come from the engine, the extensions from us.
+        let mut features = game.chess.features();
+        features.extend([
+            ext::feature(ext::CHAT),
+            ext::feature(ext::HEARTBEAT),
+            ext::feature(ext::REPLAY),
+            ext::feature(ext::TAKEBACK),
+            ext::feature(ext::REMATCH),
+            ext::feature(ext::CHECK)
+            ]);
+
+        if framing && !websocket {
+            features.push(ext::feature(ext::FRAMED));
+        }
 
-            for (p, x, y) in game.chess.get_white_positions().to_owned() {
-                board_copy[y as usize][x as usize] = match p {
-                    ludviggl_chess::Piece::Pawn => { Piece::WhitePawn }
-                    ludviggl_chess::Piece::Rook => { Piece::WhiteRook }
-                    ludviggl_chess::Piece::Knight => { Piece::WhiteKnight }
-                    ludviggl_chess::Piece::Bishop => { Piece::WhiteBishop }
-                    ludviggl_chess::Piece::King => { Piece::WhiteKing }
-                    ludviggl_chess::Piece::Queen => { Piece::WhiteQueen }
-                }
+        // Every connection has its own channel of events, the accept thread
+        // hands over the receiving ends as connections come in.
+        let (conn_tx, conn_rx) = mpsc::channel::<Receiver<HostEvent>>();
+        let mut connections: Vec<Receiver<HostEvent>> = vec![];
+
+        if has_flag(&flags, "local") {
+            start_hot_seat(&mut game, has_flag(&flags, "flip"));
+        } else if has_flag(&flags, "ai") {
+            let strategy = if flag_value(&flags, "uci").is_some() { uci_strategy(&flags) } else { bot::Strategy::Search(search_config(&flags)) };
+            start_computer(&mut game, strategy, host_white);
+        } else {
+            game.host_white = host_white;
+            game.host_turn = host_white;
+
+            if lan {
+                let beacon = discovery::Beacon::new(discovery::host_name(), 8384, host_white);
+                game.announcer = discovery::announce(beacon).map_err(|e| eprintln!("Could not announce the game: {}", e)).ok();
             }
 
-            std::mem::drop(game);
-
-            let handshake = ServerToClientHandshake {
-                features: vec![
-                    Features::EnPassant, 
-                    Features::Castling
-                    ],
-                board: board_copy,
-                moves: vec![],
-                joever: Joever::Ongoing
-            };
-        
-            serde_json::to_writer(&stream, &handshake).unwrap();
-
-            loop {
-                let mut game = network.lock().unwrap();
-                
-                match game.state {
-                    HostState::ClientPlaying => {
-                        std::mem::drop(game);
-                        let res = ClientToServer::deserialize(&mut de).unwrap().to_owned();
-                        
-                        game = network.lock().unwrap();
-
-                        match res {
-                            ClientToServer::Draw => { game.game_end = true; }
-                            ClientToServer::Resign => { game.game_end = true; }
-                            ClientToServer::Move(Move{ start_x: sx, start_y: sy, end_x: ex, end_y: ey, promotion: _ }) => {
-                                game.recieved_move = (sx, sy, ex, ey);
-                                game.state = HostState::TestingClientMove;
-                            }
-                        }
-                    }
-                    
-                    HostState::RespondToClient => {
-                        let mut board_copy = [[Piece::None; 8]; 8];
-                        for (p, x, y) in game.chess.get_black_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::BlackPawn }
-                                ludviggl_chess::Piece::Rook => { Piece::BlackRook }
-                                ludviggl_chess::Piece::Knight => { Piece::BlackKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::BlackBishop }
-                                ludviggl_chess::Piece::King => { Piece::BlackKing }
-                                ludviggl_chess::Piece::Queen => { Piece::BlackQueen }
-                            }
-                        }
-                    
-                        for (p, x, y) in game.chess.get_white_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::WhitePawn }
-                                ludviggl_chess::Piece::Rook => { Piece::WhiteRook }
-                                ludviggl_chess::Piece::Knight => { Piece::WhiteKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::WhiteBishop }
-                                ludviggl_chess::Piece::King => { Piece::WhiteKing }
-                                ludviggl_chess::Piece::Queen => { Piece::WhiteQueen }
-                            }
-                        }
-
-                        let send = ServerToClient::State { 
-                            board: board_copy, 
-                            moves: vec![], 
-                            joever: if game.game_end { Joever::Indeterminate } else { Joever::Ongoing }, 
-                            move_made: Move {
-                                start_x: game.recieved_move.0,
-                                start_y: game.recieved_move.1,
-                                end_x: game.recieved_move.2,
-                                end_y: game.recieved_move.3,
-                                promotion: Piece::None
-                            }
-                        };
-
-                        serde_json::to_writer(&stream, &send).unwrap();
-
-                        game.recieved_move = (0,0,0,0);
-                        game.host_turn = true;
-
-                        game.state =  HostState::HostPlaying;
-                    }
-
-                    HostState::BadClientMove => {
-                        let mut board_copy = [[Piece::None; 8]; 8];
-                        for (p, x, y) in game.chess.get_black_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::BlackPawn }
-                                ludviggl_chess::Piece::Rook => { Piece::BlackRook }
-                                ludviggl_chess::Piece::Knight => { Piece::BlackKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::BlackBishop }
-                                ludviggl_chess::Piece::King => { Piece::BlackKing }
-                                ludviggl_chess::Piece::Queen => { Piece::BlackQueen }
-                            }
-                        }
-                    
-                        for (p, x, y) in game.chess.get_white_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::WhitePawn }
-                                ludviggl_chess::Piece::Rook => { Piece::WhiteRook }
-                                ludviggl_chess::Piece::Knight => { Piece::WhiteKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::WhiteBishop }
-                                ludviggl_chess::Piece::King => { Piece::WhiteKing }
-                                ludviggl_chess::Piece::Queen => { Piece::WhiteQueen }
+            thread::spawn(move || {
+                let listener = TcpListener::bind(if lan { "0.0.0.0:8384" } else { "127.0.0.1:8384" }).unwrap();
+
+                // Keep listening for the whole game. The first to complete the
+                // handshake plays, later connections watch or take the seat
+                // back after a lost connection. Each handshake runs on its own
+                // thread, so one that never finishes holds up nobody else.
+                loop {
+                    let (stream, addr) = listener.accept().unwrap();
+                    let (conn_tx, tls_server, password, recorder) = (conn_tx.clone(), tls_server.clone(), password.clone(), recorder.clone());
+
+                    thread::spawn(move || {
+                        match accept_client(stream, websocket, &tls_server, password.as_deref(), &recorder) {
+                            Ok((reader, writer, des)) => {
+                                let (tx, rx) = mpsc::channel();
+                                if conn_tx.send(rx).is_ok() {
+                                    net::spawn(reader, writer, des, tx);
+                                }
                             }
-                        }
-
-                        let send = ServerToClient::Error { 
-                            board: board_copy, 
-                            moves: vec![], 
-                            joever: if game.game_end { Joever::Indeterminate } else { Joever::Ongoing }, 
-                            message: "Bad move.".to_string()
-                        };
 
-                        serde_json::to_writer(&stream, &send).unwrap();
-                        game.state = HostState::ClientPlaying;
-                    }
-
-                    HostState::SendToClient => {
-                        let mut board_copy = [[Piece::None; 8]; 8];
-
-                        for (p, x, y) in game.chess.get_black_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::BlackPawn }
-                                ludviggl_chess::Piece::Rook => { Piece::BlackRook }
-                                ludviggl_chess::Piece::Knight => { Piece::BlackKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::BlackBishop }
-                                ludviggl_chess::Piece::King => { Piece::BlackKing }
-                                ludviggl_chess::Piece::Queen => { Piece::BlackQueen }
-                            }
-                        }
-                    
-                        for (p, x, y) in game.chess.get_white_positions().to_owned() {
-                            board_copy[y as usize][x as usize] = match p {
-                                ludviggl_chess::Piece::Pawn => { Piece::WhitePawn }
-                                ludviggl_chess::Piece::Rook => { Piece::WhiteRook }
-                                ludviggl_chess::Piece::Knight => { Piece::WhiteKnight }
-                                ludviggl_chess::Piece::Bishop => { Piece::WhiteBishop }
-                                ludviggl_chess::Piece::King => { Piece::WhiteKing }
-                                ludviggl_chess::Piece::Queen => { Piece::WhiteQueen }
-                            }
+                            Err(e) => { eprintln!("Turned away {}: {}", addr, e); }
                         }
+                    });
+                }
+            });
+        }
 
-                        let send = ServerToClient::State { 
-                            board: board_copy, 
-                            moves: vec![], 
-                            joever: if game.game_end { Joever::Indeterminate } else { Joever::Ongoing }, 
-                            move_made: Move {
-                                start_x: game.made_move.0,
-                                start_y: game.made_move.1,
-                                end_x: game.made_move.2,
-                                end_y: game.made_move.3,
-                                promotion: Piece::None
-                            }
-                        };
-
-                        serde_json::to_writer(&stream, &send).unwrap();
-                        game.made_move = (usize::MAX, usize::MAX, usize::MAX, usize::MAX);
-                        game.host_turn = false;
-
-                        game.state =  HostState::ClientPlaying;
-                    }
+        while !window.should_close() {
+            connections.extend(conn_rx.try_iter());
 
-                    _ => { }
+            for id in 0..connections.len() {
+                let pending: Vec<HostEvent> = connections[id].try_iter().collect();
+                for event in pending {
+                    host_on_event(&mut game, id, event, &features);
                 }
+            }
+
+            computer_answers(&mut game);
 
-                std::mem::drop(game);
-                std::thread::sleep(std::time::Duration::from_millis(7));
+            if let Some(outbox) = &game.outbox {
+                game.heartbeat.tick(outbox);
             }
-        });
 
-        while !window.should_close() {
-            let mut game = render.lock().unwrap();
+            if !game.game_end && game.heartbeat.expired() {
+                host_abandon(&mut game);
+            }
 
             unsafe {
                 gl::ClearColor(0.3, 0.3, 0.2, 1.0);
@@ -712,109 +842,154 @@ fn main() {
                 
                 tile_shader.use_program();
                 tile_shader.set_mat4("projection", proj);
+                let flipped = game.flipped;
                 for i in 0..64 {
+                    game.board[i].transform.translation = square_translation(i % 8, i / 8, flipped);
                     game.board[i].draw(&tile_shader);
                 }
+                game.chat.draw_box(&tile_shader);
 
                 piece_shader.use_program();
                 piece_shader.set_mat4("projection", proj);
                 gl::BindTexture(gl::TEXTURE_2D, sprites);
-                for (piece, x, y) in game.chess.get_white_positions().to_owned() {
-                    game.white_pieces[piece as usize].transform.translation.x = x as f32 - 3.5;
-                    game.white_pieces[piece as usize].transform.translation.y = -1.0 * (-(y as f32) + 3.5);
-                    game.white_pieces[piece as usize].draw(&piece_shader);
-                }
-
-                for (piece, x, y) in game.chess.get_black_positions().to_owned() {
-                    game.black_pieces[piece as usize].transform.translation.x = x as f32 - 3.5;
-                    game.black_pieces[piece as usize].transform.translation.y = -1.0 * (-(y as f32) + 3.5);
-                    game.black_pieces[piece as usize].draw(&piece_shader);
-                }
+                let pieces = BoardState::from_protocol(match &game.analysis { Some(analysis) => analysis.board(), None => game.chess.board() });
+                draw_pieces(&pieces, &mut game.white_pieces, &mut game.black_pieces, &piece_shader, flipped);
                 gl::BindTexture(gl::TEXTURE_2D, 0);
 
                 text_shader.use_program();
                 text_shader.set_mat4("projection", text_proj);
                 if game.game_end {
-                    let winner = if game.host_turn { "You lose!" } else { "You win!" };
+                    let winner = if game.hot_seat {
+                        match game.joever {
+                            Joever::White => "White wins!",
+                            Joever::Black => "Black wins!",
+                            _ => "Draw!"
+                        }
+                    } else if game.timed_out && game.joever == Joever::Indeterminate {
+                        "Adjourned."
+                    } else if game.computer_failed {
+                        "Game over."
+                    } else {
+                        result_text(&game.joever, game.host_white, false).unwrap_or(if game.host_turn { "You lose!" } else { "You win!" })
+                    };
                     render_text(&text_shader, winner.to_string(), 800.0, 640.0, 0.7, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
                     render_text(&text_shader, "Press \'ESC\' to exit.".to_string(), 800.0, 610.0, 0.44, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    if game.timed_out {
+                        render_text(&text_shader, format!("The {} timed out.", game.opponent.to_lowercase()), 800.0, 580.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+                    if game.computer_failed {
+                        render_text(&text_shader, "The computer has no move.".to_string(), 800.0, 580.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                    }
+                    render_analysis(&text_shader, &game.analysis, &characters, &mut char_quad);
+
+                    if game.outbox.is_some() || game.computer.is_some() || game.hot_seat {
+                        render_rematch(&text_shader, &game.rematch, game.opponent, &characters, &mut char_quad);
+                    }
+                } else if game.promoting {
+                    render_text(&text_shader, "Select promotion:".to_string(), 800.0, 640.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "1: Rook".to_string(), 800.0, 610.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "2: Knight".to_string(), 800.0, 580.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "3: Bishop".to_string(), 800.0, 550.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
+                    render_text(&text_shader, "4: Queen".to_string(), 800.0, 520.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
                 } else {
-                    let turn = if game.host_turn { "You are playing." } else { "Client is playing." };
-                    render_text(&text_shader, turn.to_string(),  800.0, 640.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
-                }
-            }
+                    let turn = if game.hot_seat {
+                        if game.host_white { "White to move.".to_string() } else { "Black to move.".to_string() }
+                    } else if game.host_turn {
+                        "You are playing.".to_string()
+                    } else {
+                        format!("{} is playing.", game.opponent)
+                    };
+                    render_text(&text_shader, turn,  800.0, 640.0, 0.5, vec4(1.0, 1.0, 1.0, 1.0), &characters, &mut char_quad);
 
-            match game.chess.get_state() {
-                ludviggl_chess::State::SelectPiece => {
-                    if game.state == HostState::TestingClientMove {
-                        let m = (game.recieved_move.0 as u8, game.recieved_move.1 as u8);
-                        game.chess.select_piece(m.0, m.1).unwrap();
-                        
-                        if game.chess.get_state() as i8 == ludviggl_chess::State::SelectPiece as i8 {
-                            game.state = HostState::BadClientMove;
-                        }
+                    if game.in_check {
+                        render_text(&text_shader, "Check!".to_string(), 800.0, 565.0, 0.45, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
                     }
-                }
 
-                ludviggl_chess::State::SelectMove => {
-                    if game.state == HostState::TestingClientMove {
-                        let p_player = game.chess.get_current_player() as i8;
-                        let m = (game.recieved_move.2 as u8, game.recieved_move.3 as u8);
-                        game.chess.select_move(m.0, m.1).unwrap();
-
-                        if game.chess.get_state() as u8 == 3 {
-                            game.state = HostState::PromotingClient;
-                        } else if p_player == game.chess.get_current_player() as i8 {
-                            game.state = HostState::BadClientMove;
-                        } else {
-                            game.state = HostState::RespondToClient;
-                        }
+                    if !game.hot_seat {
+                        render_takeback(&text_shader, &game.takeback, host_has_moved(&game), game.opponent, &characters, &mut char_quad);
+                    }
+
+                    if let Some(left) = game.heartbeat.countdown() {
+                        render_text(&text_shader, format!("The {} is silent.", game.opponent.to_lowercase()), 800.0, 610.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
+                        render_text(&text_shader, format!("Giving up in {}s.", left), 800.0, 590.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), &characters, &mut char_quad);
                     }
                 }
 
-                _ => { }
-            }
+                render_text(&text_shader, format!("Engine: {}", game.chess.name()), 810.0, 770.0, 0.3, vec4(0.8, 0.8, 0.8, 1.0), &characters, &mut char_quad);
 
-            match game.chess.get_state() {
-                ludviggl_chess::State::CheckMate => {
-                    game.game_end = true;
+                if game.computer.is_none() && !game.hot_seat {
+                    render_status(&text_shader, game.status, &game.heartbeat, &game.peer, &characters, &mut char_quad);
                 }
 
-                ludviggl_chess::State::SelectPromotion => {
-                    game.chess.select_promotion(ludviggl_chess::Piece::Queen).unwrap();
-                    if game.state == HostState::PromotingClient {
-                        game.state = HostState::RespondToClient;
-                    } else {
-                        game.state = HostState::SendToClient;
-                    }
+                game.chat.draw_text(&text_shader, &characters, &mut char_quad);
+
+                if audio.available() {
+                    let sound = if audio.muted { "M: Unmute sound" } else { "M: Mute sound" };
+                    render_text(&text_shader, sound.to_string(), 810.0, 20.0, 0.35, vec4(0.8, 0.8, 0.8, 1.0), &characters, &mut char_quad);
                 }
+            }
 
-                _ => { }
+            for sound in game.sounds.drain(..) {
+                audio.play(sound);
             }
 
             for (_, event) in flush_messages(&events) {
+                if game.chat.handle_event(&event) {
+                    continue;
+                }
+
                 match event {
                     WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
                         window.set_should_close(true);
                     }
 
+                    WindowEvent::Key(Key::M, _, Action::Press, _) => {
+                        audio.toggle_mute();
+                    }
+
                     WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                        game.chat.on_click(window.get_cursor_pos());
+
                         if !game.game_end && !game.promoting && game.state == HostState::HostPlaying && game.client_connected {
                             host_on_pick(&mut game, &window);
                         }
+
+                        if game.game_end {
+                            if let Some((x, y)) = square_at(window.get_cursor_pos(), game.flipped) {
+                                let analysis = start_analysis(&mut game.analysis, &game.history);
+                                analysis.pick(x, y);
+                                analysis.colour(&mut game.board);
+                            }
+                        }
+                    }
+
+                    WindowEvent::Key(key @ (Key::T | Key::Y | Key::N | Key::R), _, Action::Press, _) => {
+                        host_on_key(&mut game, key);
+                    }
+
+                    WindowEvent::Key(key @ (Key::Left | Key::Right | Key::Home | Key::End), _, Action::Press | Action::Repeat, _) if game.game_end => {
+                        let analysis = start_analysis(&mut game.analysis, &game.history);
+                        analysis_key(analysis, key);
+                        analysis.colour(&mut game.board);
                     }
 
                     _ => {}
                 }
 
                 if game.promoting && game.host_turn {
-                    match event {
-                        WindowEvent::Key(Key::Num1, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Rook; }
-                        WindowEvent::Key(Key::Num2, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Knight; }
-                        WindowEvent::Key(Key::Num3, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Bishop; }
-                        WindowEvent::Key(Key::Num4, _, Action::Press, _) => { game.selected_prom = ludviggl_chess::Piece::Queen; }
-
-                        _ => { }
+                    let white = game.host_white;
+                    game.selected_prom = match event {
+                        WindowEvent::Key(Key::Num1, _, Action::Press, _) => { if white { Piece::WhiteRook } else { Piece::BlackRook } }
+                        WindowEvent::Key(Key::Num2, _, Action::Press, _) => { if white { Piece::WhiteKnight } else { Piece::BlackKnight } }
+                        WindowEvent::Key(Key::Num3, _, Action::Press, _) => { if white { Piece::WhiteBishop } else { Piece::BlackBishop } }
+                        WindowEvent::Key(Key::Num4, _, Action::Press, _) => { if white { Piece::WhiteQueen } else { Piece::BlackQueen } }
+
+                        _ => { Piece::None }
+                    };
+
+                    if game.selected_prom != Piece::None {
+                        game.promoting = false;
+                        host_play(&mut game);
                     }
                 }
             }
@@ -822,94 +997,1370 @@ fn main() {
             window.swap_buffers();
             glfw.poll_events();
 
-            std::mem::drop(game);
             std::thread::sleep(std::time::Duration::from_millis(7));
         }
     }
 }
 
-fn host_on_pick(game: &mut HostGame, window: &Window) {
-    let cursor = window.get_cursor_pos();
-    
-    if cursor.0 <= 800.0 { 
-        let x: usize = (((cursor.0 as f32 * 8.0)) / 800.0).floor() as usize;
-        let y: usize = 7 - (((cursor.1 as f32 * 8.0)) / 800.0).floor() as usize;
+fn has_flag(flags: &[String], name: &str) -> bool {
+    return flags.iter().any(|f| f.trim_start_matches("--") == name);
+}
 
-        for i in 0..64 {
-            game.board[i].color = game.board[i].default_color;
-        }
+fn flag_value(flags: &[String], name: &str) -> Option<String> {
+    return flags.iter()
+        .filter_map(|f| f.trim_start_matches("--").split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string());
+}
 
-        match game.chess.get_state() {
-            ludviggl_chess::State::SelectPiece => {
-                game.chess.select_piece(x as u8, y as u8).unwrap();
+fn heartbeat_config(flags: &[String]) -> Heartbeat {
+    let seconds = flag_value(flags, "timeout").and_then(|v| v.parse::<u64>().ok()).unwrap_or(30);
+    let name = flag_value(flags, "on-timeout").unwrap_or("award".to_string());
+    let rule = Abandon::from_name(&name)
+        .unwrap_or_else(|| panic!("Unknown timeout rule \"{}\", expected award or adjourn.", name));
 
-                if game.chess.get_state() as i8 == ludviggl_chess::State::SelectPiece as i8 {
-                } else {
-                    game.made_move.0 = x;
-                    game.made_move.1 = y;
+    return Heartbeat::new(std::time::Duration::from_secs(seconds), rule);
+}
 
-                }
+fn search_config(flags: &[String]) -> ai::SearchConfig {
+    let mut config = ai::SearchConfig::new();
 
-                if let Ok(moves) = game.chess.get_moves() {
-                    game.board[8*y as usize + x as usize].color =
-                        game.board[8*y as usize + x as usize].color +
-                        Vec4{ x: 0.4, y: 0.4, z: 0.0, w: 0.0 };
-                    
-                    for &(a, b) in moves {
-                        game.board[8*b as usize + a as usize].color =
-                            game.board[8*b as usize + a as usize].color +
-                            Vec4{ x: 0.0, y: 0.4, z: 0.0, w: 0.0 };
-                    }
-                }
-            }
+    if let Some(depth) = flag_value(flags, "ai-depth").and_then(|v| v.parse::<u32>().ok()) {
+        config.depth = depth;
+    }
 
-            ludviggl_chess::State::SelectMove => {
-                let p_player = game.chess.get_current_player() as i8;
-                game.chess.select_move(x as u8, y as u8).unwrap();
+    if let Some(ms) = flag_value(flags, "ai-time").and_then(|v| v.parse::<u64>().ok()) {
+        config.time_budget = std::time::Duration::from_millis(ms);
+    }
 
-                if p_player == game.chess.get_current_player() as i8 {
-                } else {
-                    game.made_move.2 = x;
-                    game.made_move.3 = y;
-                    if game.chess.get_state() as i8 == 3 {
-                        game.state = HostState::PromotingHost;
-                    } else {
-                        game.state = HostState::SendToClient;
-                    }
-                }
-            }
+    return config;
+}
 
-            _ => { }
-        }
+fn uci_strategy(flags: &[String]) -> bot::Strategy {
+    let path = flag_value(flags, "uci").unwrap_or_else(|| panic!("Pass the engine to run with --uci=<path>."));
+    let engine = UciEngine::launch(&path, search_config(flags).time_budget)
+        .unwrap_or_else(|e| panic!("Could not start UCI engine \"{}\": {}", path, e));
+
+    println!("Using UCI engine {}.", engine.name);
+    return bot::Strategy::Uci(engine);
+}
+
+fn update_result(game: &mut HostGame) {
+    game.joever = game.chess.result();
+    game.in_check = game.chess.in_check();
+
+    if game.joever != Joever::Ongoing && !game.game_end {
+        game.game_end = true;
+        game.sounds.push(Sound::GameOver);
     }
 }
 
-fn client_on_pick(game: &mut ClientGame, window: &Window) {
-    let cursor = window.get_cursor_pos();
-    
-    if cursor.0 <= 800.0 { 
-        let x: usize = (((cursor.0 as f32 * 8.0)) / 800.0).floor() as usize;
-        let y: usize = 7 - (((cursor.1 as f32 * 8.0)) / 800.0).floor() as usize;
+fn result_text(joever: &Joever, white: bool, stalemate: bool) -> Option<&'static str> {
+    return match joever {
+        Joever::White => Some(if white { "You win!" } else { "You lose!" }),
+        Joever::Black => Some(if white { "You lose!" } else { "You win!" }),
+        Joever::Draw => Some(if stalemate { "Stalemate!" } else { "Draw!" }),
+        _ => None
+    };
+}
 
-        if game.m_from == (usize::MAX, usize::MAX) {
-            game.board[y*8 + x].color = vec4(1.0, 0.0, 0.0, 1.0);
+fn accept_client(stream: TcpStream, websocket: bool, tls: &Option<Arc<rustls::ServerConfig>>, password: Option<&str>, recorder: &Option<Recorder>) -> std::io::Result<(wire::Reader, wire::Writer, ClientToServerHandshake)> {
+    // Covers the TLS and WebSocket handshakes, the reader takes over after.
+    stream.set_read_timeout(Some(wire::HANDSHAKE_TIMEOUT))?;
 
-            game.m_from = (x, y);
+    let (mut reader, mut writer) = match tls {
+        _ if websocket => wire::accept_websocket(stream)?,
+        Some(config) => secure::accept_tls(stream, config)?,
+        None => wire::over_tcp(&stream)?
+    };
+
+    if let Some(recorder) = recorder {
+        wire::record(&mut reader, &mut writer, recorder);
+    }
+
+    reader.set_timeout(Some(wire::HANDSHAKE_TIMEOUT))?;
+
+    let handshake = reader.read()?;
+
+    if let Some(password) = password {
+        secure::challenge(&mut reader, &writer, password)?;
+    }
+
+    reader.set_timeout(None)?;
+
+    return Ok((reader, writer, handshake));
+}
+
+// Connects and does the handshake off the render thread. The game starts
+// once the connection arrives as an event, a failure arrives as Closed.
+fn spawn_connect(address: &str, white: bool, tls: &Option<Arc<rustls::ClientConfig>>, password: &Option<String>, framing: bool, recorder: &Option<Recorder>) -> Receiver<ClientEvent> {
+    let (tx, rx) = mpsc::channel();
+    let (address, tls, password, recorder) = (address.to_string(), tls.clone(), password.clone(), recorder.clone());
+
+    thread::spawn(move || {
+        if let Err(e) = connect_to_host(&address, white, &tls, password.as_deref(), framing, &recorder, tx.clone()) {
+            let _ = tx.send(net::Event::Closed(e.to_string()));
+        }
+    });
+
+    return rx;
+}
+
+fn connect_to_host(address: &str, white: bool, tls: &Option<Arc<rustls::ClientConfig>>, password: Option<&str>, framing: bool, recorder: &Option<Recorder>, events: Sender<ClientEvent>) -> std::io::Result<()> {
+    let target = address.to_socket_addrs()?.next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "unknown address"))?;
+    let stream = TcpStream::connect_timeout(&target, std::time::Duration::from_secs(10))?;
+    stream.set_read_timeout(Some(wire::HANDSHAKE_TIMEOUT))?;
+
+    let (mut reader, mut writer) = match tls {
+        Some(config) => secure::connect_tls(stream, config)?,
+        None => wire::over_tcp(&stream)?
+    };
+
+    if let Some(recorder) = recorder {
+        wire::record(&mut reader, &mut writer, recorder);
+    }
+
+    let handshake = ClientToServerHandshake {
+        server_color: if white { Color::Black } else { Color::White }
+    };
+
+    writer.write(&handshake)?;
+
+    reader.set_timeout(Some(wire::HANDSHAKE_TIMEOUT))?;
+    let des = secure::read_handshake(&mut reader, &writer, password)?;
+    reader.set_timeout(None)?;
+
+    let framed = framing && ext::supports(&des.features, ext::FRAMED);
+
+    let mut accepted = vec![];
+    if ext::supports(&des.features, ext::CHAT) {
+        accepted.push(ext::CHAT.to_string());
+    }
+    if ext::supports(&des.features, ext::HEARTBEAT) {
+        accepted.push(ext::HEARTBEAT.to_string());
+    }
+    if ext::supports(&des.features, ext::REPLAY) {
+        accepted.push(ext::REPLAY.to_string());
+    }
+    if ext::supports(&des.features, ext::TAKEBACK) {
+        accepted.push(ext::TAKEBACK.to_string());
+    }
+    if ext::supports(&des.features, ext::REMATCH) {
+        accepted.push(ext::REMATCH.to_string());
+    }
+    if ext::supports(&des.features, ext::CHECK) {
+        accepted.push(ext::CHECK.to_string());
+    }
+    if framed {
+        accepted.push(ext::FRAMED.to_string());
+    }
+
+    if !accepted.is_empty() {
+        writer.write(&Extension::Features(accepted))?;
+    }
+
+    // Everything after the acknowledgement is framed if we asked for it.
+    writer.set_framed(framed);
+
+    net::spawn(reader, writer, des, events);
+    return Ok(());
+}
+
+fn host_on_event(game: &mut HostGame, id: usize, event: HostEvent, features: &[Features]) {
+    if game.player != Some(id) {
+        if let net::Event::Connected(des, outbox) = &event {
+            // A player that lost the connection may come back for its colour
+            // until the timeout gives the game away. Only a connection that
+            // is closed or has gone quiet counts as lost, anyone else asking
+            // for the seat of a live player gets to watch. A quiet connection
+            // may not have noticed it is gone yet, it is hung up on.
+            let colour = (des.server_color == Color::White) == game.host_white;
+            let lost = game.outbox.is_none() || game.heartbeat.countdown().is_some() || game.heartbeat.expired();
+            let returning = game.player.is_some() && !game.game_end && colour && lost;
+
+            // The host picked its colour before anyone connected, and the
+            // LAN announcement already told everyone which one it is.
+            if game.player.is_none() && !game.client_connected && !colour {
+                let host = if game.host_white { "white" } else { "black" };
+                let message = format!("The host plays {}, ask for the other colour.", host);
+                let _ = outbox.send(&ServerToClient::Error { board: game.chess.board(), moves: vec![], joever: Joever::Ongoing, message: message });
+                eprintln!("{} asked to play {} as well, refused.", outbox.peer().map(|a| a.to_string()).unwrap_or("A client".to_string()), host);
+                outbox.close();
+                return;
+            }
+
+            if returning {
+                if let Some(stale) = game.outbox.take() {
+                    stale.close();
+                }
+            }
+
+            if game.player.is_none() || returning {
+                game.player = Some(id);
+            } else {
+                let _ = outbox.send(&handshake(game, features));
+                eprintln!("{} is watching the game.", outbox.peer().map(|a| a.to_string()).unwrap_or("A spectator".to_string()));
+                game.spectators.push(Spectator { id: id, outbox: outbox.clone(), accepted: vec![] });
+                return;
+            }
+        } else {
+            spectator_on_event(game, id, event);
+            return;
+        }
+    }
+
+    match event {
+        net::Event::Connected(des, outbox) => {
+            let _ = outbox.send(&handshake(game, features));
+            game.accepted.clear();
+            game.status = Status::Connected;
+            game.peer = outbox.peer().map(|a| a.to_string());
+            game.outbox = Some(outbox);
+            game.announcer = None;
+            game.heartbeat.heard();
+
+            // Coming back to a game in progress.
+            if game.client_connected {
+                return;
+            }
+
+            game.host_turn = if des.server_color == Color::White { true } else { false };
+            game.host_white = game.host_turn;
+            game.client_connected = true;
+            game.state = if game.host_turn { HostState::HostPlaying } else { HostState::ClientPlaying };
+        }
+
+        net::Event::Message(message) => {
+            game.heartbeat.heard();
+            host_on_message(game, message);
+        }
+
+        net::Event::Extension(e) => {
+            let Some(outbox) = game.outbox.clone() else {
+                return;
+            };
+
+            game.heartbeat.heard();
+
+            match &e {
+                Extension::Features(accepted) => {
+                    game.accepted = accepted.clone();
+
+                    if ext::supports(features, ext::FRAMED) && accepted.iter().any(|f| f == ext::FRAMED) {
+                        outbox.set_framed(true);
+                    }
+
+                    if accepted.iter().any(|f| f == ext::HEARTBEAT) {
+                        game.heartbeat.enabled = true;
+                    }
+
+                    if accepted.iter().any(|f| f == ext::TAKEBACK) {
+                        game.takeback.enabled = true;
+                    }
+
+                    if accepted.iter().any(|f| f == ext::REMATCH) {
+                        game.rematch.enabled = true;
+                    }
+
+                    if accepted.iter().any(|f| f == ext::REPLAY) && !game.log.is_empty() {
+                        send_replay(&outbox, &game.log, false);
+                    }
+                }
+
+                Extension::TakebackRequest => {
+                    game.takeback.asked = true;
+
+                    if game.game_end {
+                        game.takeback.refuse(&outbox);
+                    }
+                }
+
+                Extension::TakebackAnswer { accepted } => {
+                    if game.takeback.sent && *accepted {
+                        take_back(game, true);
+                    } else if game.takeback.sent {
+                        game.takeback.sent = false;
+                        game.takeback.refused = true;
+                    }
+                }
+
+                Extension::RematchOffer => {
+                    game.rematch.asked = true;
+
+                    if !game.game_end {
+                        game.rematch.decline(&outbox);
+                    } else if game.rematch.sent {
+                        start_rematch(game);
+                    }
+                }
+
+                Extension::RematchDeclined => {
+                    if game.rematch.sent {
+                        game.rematch.sent = false;
+                        game.rematch.declined = true;
+                    }
+                }
+
+                _ => { }
+            }
+
+            game.heartbeat.on_extension(&e, &outbox);
+            game.chat.on_extension(e, &outbox);
+        }
+
+        net::Event::Closed(reason) => {
+            // The reader and the writer both report it.
+            if game.outbox.take().is_some() {
+                eprintln!("Lost the connection to the client: {}", reason);
+                game.status = Status::Disconnected;
+            }
+        }
+    }
+}
+
+// Spectators get every state the player gets but can not move or chat.
+fn spectator_on_event(game: &mut HostGame, id: usize, event: HostEvent) {
+    match event {
+        net::Event::Message(ClientToServer::Move(_)) => {
+            let send = ServerToClient::Error {
+                board: game.chess.board(),
+                moves: vec![],
+                joever: game.joever.clone(),
+                message: "You are watching the game.".to_string()
+            };
+
+            if let Some(outbox) = game.spectator(id) {
+                let _ = outbox.send(&send);
+            }
+        }
+
+        net::Event::Extension(e) => {
+            if let (Extension::Features(accepted), Some(spectator)) = (&e, game.spectators.iter_mut().find(|s| s.id == id)) {
+                spectator.accepted = accepted.clone();
+            }
+
+            let Some(outbox) = game.spectator(id) else {
+                return;
+            };
+
+            match e {
+                Extension::Features(accepted) => {
+                    if accepted.iter().any(|f| f == ext::FRAMED) {
+                        outbox.set_framed(true);
+                    }
+
+                    if accepted.iter().any(|f| f == ext::REPLAY) {
+                        send_replay(outbox, &game.log, true);
+                    }
+                }
+
+                Extension::Ping { id } => {
+                    let _ = outbox.send(&Extension::Pong { id: id });
+                }
+
+                _ => { }
+            }
+        }
+
+        net::Event::Closed(_) => {
+            game.spectators.retain(|s| s.id != id);
+        }
+
+        _ => { }
+    }
+}
+
+fn handshake(game: &mut HostGame, features: &[Features]) -> ServerToClientHandshake {
+    return ServerToClientHandshake {
+        features: features.to_vec(),
+        board: game.chess.board(),
+        moves: game.chess.legal_moves(),
+        joever: game.joever.clone()
+    };
+}
+
+fn send_replay(outbox: &Outbox, log: &[ServerToClient], spectator: bool) {
+    let _ = outbox.send(&Extension::Replay { states: log.len(), spectator: spectator });
+
+    for state in log {
+        let _ = outbox.send(state);
+    }
+}
+
+// Sends the state after a move and keeps it for late joiners.
+fn send_state(game: &mut HostGame, m: Move) {
+    let send = ServerToClient::State {
+        board: game.chess.board(),
+        moves: game.chess.legal_moves(),
+        joever: game.joever.clone(),
+        move_made: m
+    };
+
+    game.broadcast(&send);
+    game.log.push(send);
+
+    game.broadcast_extension(ext::CHECK, &Extension::Check { in_check: game.in_check });
+}
+
+fn host_on_message(game: &mut HostGame, message: ClientToServer) {
+    match message {
+        ClientToServer::Draw => {
+            game.game_end = true;
+            game.joever = Joever::Draw;
+            game.broadcast(&ServerToClient::Resigned { board: game.chess.board(), joever: game.joever.clone() });
+        }
+
+        ClientToServer::Resign => {
+            game.game_end = true;
+            game.joever = if game.host_white { Joever::White } else { Joever::Black };
+            game.broadcast(&ServerToClient::Resigned { board: game.chess.board(), joever: game.joever.clone() });
+        }
+
+        ClientToServer::Move(m) => {
+            let before = game.chess.board();
+
+            if game.state != HostState::ClientPlaying || !game.chess.play(&m) {
+                let send = ServerToClient::Error { 
+                    board: game.chess.board(), 
+                    moves: game.chess.legal_moves(), 
+                    joever: game.joever.clone(), 
+                    message: "Bad move.".to_string()
+                };
+
+                game.send(&send);
+                wake_computer(game);
+                return;
+            }
+
+            let sound = Sound::for_move(&before, &game.chess.board(), &m);
+            game.sounds.push(sound);
+            game.history.push(m.clone());
+            game.takeback.refused = false;
+            update_result(game);
+            send_state(game, m);
+
+            game.host_turn = true;
+            game.state = HostState::HostPlaying;
+            reset_tiles(game);
+        }
+    }
+}
+
+// Plays the client's side locally. The search runs on its own thread so the
+// render loop keeps going while the computer thinks, and its moves are
+// played as if a client had sent them.
+fn start_computer(game: &mut HostGame, mut strategy: bot::Strategy, host_white: bool) {
+    let (tx, rx) = mpsc::channel::<(u64, Position, Vec<Move>)>();
+    let (answer_tx, answers) = mpsc::channel();
+
+    thread::spawn(move || {
+        for (asked, position, moves) in rx {
+            if answer_tx.send((asked, strategy.choose(&position, &moves))).is_err() {
+                break;
+            }
+        }
+    });
+
+    game.host_white = host_white;
+    game.host_turn = host_white;
+    game.client_connected = true;
+    game.opponent = "Computer";
+    game.takeback.enabled = true;
+    game.rematch.enabled = true;
+    game.state = if game.host_turn { HostState::HostPlaying } else { HostState::ClientPlaying };
+    game.computer = Some(Computer { tx: tx, answers: answers, asked: 0 });
+
+    wake_computer(game);
+}
+
+// Called whenever the position changed. Whatever the computer was asked
+// before is out of date then, even when it is not its turn now.
+fn wake_computer(game: &mut HostGame) {
+    let Some(computer) = &mut game.computer else {
+        return;
+    };
+
+    computer.asked += 1;
+
+    if game.state != HostState::ClientPlaying || game.game_end {
+        return;
+    }
+
+    // The board alone loses castling rights and en passant.
+    let mut position = Position::new();
+    for m in &game.history {
+        position.apply(m);
+    }
+    let moves = game.chess.legal_moves();
+
+    let _ = computer.tx.send((computer.asked, position, moves));
+}
+
+fn computer_answers(game: &mut HostGame) {
+    let Some(computer) = &game.computer else {
+        return;
+    };
+
+    let asked = computer.asked;
+    let answers: Vec<Option<Move>> = computer.answers.try_iter()
+        .filter(|(question, _)| *question == asked)
+        .map(|(_, answer)| answer)
+        .collect();
+
+    for answer in answers {
+        match answer {
+            Some(m) => { host_on_message(game, ClientToServer::Move(m)); }
+            None => { computer_failed(game, "The computer has no move to make or stopped answering.".to_string()); }
+        }
+    }
+}
+
+// The computer never minds a takeback, anyone else is asked first.
+fn host_ask_takeback(game: &mut HostGame) {
+    if game.game_end || game.hot_seat || !host_has_moved(game) {
+        return;
+    }
+
+    if game.computer.is_some() {
+        take_back(game, true);
+    } else if let Some(outbox) = &game.outbox {
+        game.takeback.request(outbox);
+    }
+}
+
+// Only a move of our own can be taken back.
+fn host_has_moved(game: &HostGame) -> bool {
+    return game.history.len() >= if game.host_white { 1 } else { 2 };
+}
+
+// The computer and the other player at the same screen always want another
+// game, anyone else is asked first.
+fn host_offer_rematch(game: &mut HostGame) {
+    if !game.game_end {
+        return;
+    }
+
+    if game.hot_seat || game.computer.is_some() || game.rematch.asked {
+        start_rematch(game);
+    } else if let Some(outbox) = &game.outbox {
+        game.rematch.offer(outbox);
+    }
+}
+
+// Starts over on the same connection with the colours swapped.
+fn start_rematch(game: &mut HostGame) {
+    game.rematch.done();
+    game.takeback.done();
+    game.takeback.refused = false;
+
+    game.chess = game.chess.new_game();
+    game.history.clear();
+    game.log.clear();
+    game.analysis = None;
+
+    game.host_white = if game.hot_seat { true } else { !game.host_white };
+    game.host_turn = game.host_white || game.hot_seat;
+    game.flipped = false;
+    game.state = if game.host_turn { HostState::HostPlaying } else { HostState::ClientPlaying };
+
+    game.game_end = false;
+    game.joever = Joever::Ongoing;
+    game.timed_out = false;
+    game.computer_failed = false;
+    game.promoting = false;
+    game.selected_prom = Piece::None;
+    game.made_move = (usize::MAX, usize::MAX, usize::MAX, usize::MAX);
+    game.in_check = false;
+    reset_tiles(game);
+
+    game.heartbeat.heard();
+
+    let send = Extension::NewGame { client_white: !game.host_white, board: game.chess.board(), moves: game.chess.legal_moves() };
+    game.broadcast_extension(ext::REMATCH, &send);
+
+    wake_computer(game);
+}
+
+// Takes back the last move of whoever asked, and the answer to it when there
+// is one, so it is their turn again.
+fn take_back(game: &mut HostGame, host_asked: bool) {
+    game.takeback.done();
+
+    let plies = if game.host_turn == host_asked { 2 } else { 1 };
+    if game.game_end || plies > game.history.len() {
+        // The client still waits for an answer.
+        if !host_asked {
+            game.send(&Extension::TakebackAnswer { accepted: false });
+        }
+        return;
+    }
+
+    let keep = game.history.len() - plies;
+    game.history.truncate(keep);
+    game.log.truncate(game.log.len().saturating_sub(plies));
+
+    // Engines can not undo, the game is played again up to there instead.
+    let mut chess = game.chess.new_game();
+    for m in &game.history {
+        chess.play(m);
+    }
+    game.chess = chess;
+
+    if plies % 2 == 1 {
+        game.host_turn = !game.host_turn;
+    }
+    game.state = if game.host_turn { HostState::HostPlaying } else { HostState::ClientPlaying };
+
+    game.made_move = (usize::MAX, usize::MAX, usize::MAX, usize::MAX);
+    game.selected_prom = Piece::None;
+    game.promoting = false;
+    game.in_check = game.chess.in_check();
+    reset_tiles(game);
+
+    let send = Extension::TakenBack { plies: plies, board: game.chess.board(), moves: game.chess.legal_moves() };
+    game.broadcast_extension(ext::TAKEBACK, &send);
+    game.broadcast_extension(ext::CHECK, &Extension::Check { in_check: game.in_check });
+
+    wake_computer(game);
+}
+
+// Both colours are played from the host's window, it just changes sides
+// after every move.
+fn start_hot_seat(game: &mut HostGame, flip: bool) {
+    game.hot_seat = true;
+    game.flip = flip;
+    game.rematch.enabled = true;
+    game.client_connected = true;
+    game.state = HostState::HostPlaying;
+}
+
+// Without a move from the computer the game can not go on.
+fn computer_failed(game: &mut HostGame, reason: String) {
+    if game.game_end {
+        return;
+    }
+
+    game.joever = Joever::Indeterminate;
+    game.game_end = true;
+    game.computer_failed = true;
+    game.promoting = false;
+    game.sounds.push(Sound::GameOver);
+
+    let send = ServerToClient::Resigned { board: game.chess.board(), joever: game.joever.clone() };
+    game.broadcast(&send);
+
+    eprintln!("{}", reason);
+}
+
+// The client has been silent for longer than the timeout allows.
+fn host_abandon(game: &mut HostGame) {
+    game.joever = match game.heartbeat.rule {
+        Abandon::Award => if game.host_white { Joever::White } else { Joever::Black },
+        Abandon::Adjourn => Joever::Indeterminate
+    };
+
+    game.game_end = true;
+    game.timed_out = true;
+    game.promoting = false;
+    game.sounds.push(Sound::GameOver);
+
+    // Should it only be slow, it still learns how the game ended.
+    let send = ServerToClient::Resigned { board: game.chess.board(), joever: game.joever.clone() };
+    game.broadcast(&send);
+
+    if let Some(outbox) = game.outbox.take() {
+        outbox.close();
+    }
+
+    game.status = Status::Disconnected;
+    eprintln!("The client did not answer for {} seconds.", game.heartbeat.timeout.as_secs());
+}
+
+// Plain tiles, apart from the king of the side to move when it is in check.
+fn reset_tiles(game: &mut HostGame) {
+    for i in 0..64 {
+        game.board[i].color = game.board[i].default_color;
+    }
+
+    if game.in_check {
+        let white = game.host_turn == game.host_white;
+        tint_check(&mut game.board, &game.chess.board(), white);
+    }
+}
+
+fn tint_check(tiles: &mut [Model2D; 64], board: &Board, white: bool) {
+    if let Some((x, y)) = rules::king_square(board, white) {
+        tiles[y*8 + x].color = vec4(0.9, 0.2, 0.2, 1.0);
+    }
+}
+
+fn square_translation(x: usize, y: usize, flipped: bool) -> Vec2 {
+    let (x, y) = if flipped { (7 - x, 7 - y) } else { (x, y) };
+    return Vec2{ x: x as f32 - 3.5, y: y as f32 - 3.5 };
+}
+
+// The square under the cursor, if it is over the board.
+fn square_at(cursor: (f64, f64), flipped: bool) -> Option<(usize, usize)> {
+    if cursor.0 < 0.0 || cursor.0 >= 800.0 || cursor.1 < 0.0 || cursor.1 >= 800.0 {
+        return None;
+    }
+
+    let x = (cursor.0 * 8.0 / 800.0).floor() as usize;
+    let y = 7 - (cursor.1 * 8.0 / 800.0).floor() as usize;
+
+    return Some(if flipped { (7 - x, 7 - y) } else { (x, y) });
+}
+
+// Analysis starts with the first key or click once the game is over.
+fn start_analysis<'a>(analysis: &'a mut Option<Analysis>, moves: &[Move]) -> &'a mut Analysis {
+    return analysis.get_or_insert_with(|| Analysis::new(moves.to_vec()));
+}
+
+fn analysis_key(analysis: &mut Analysis, key: Key) {
+    match key {
+        Key::Left => { analysis.back(); }
+        Key::Right => { analysis.forward(); }
+        Key::Home => { analysis.first(); }
+        Key::End => { analysis.last(); }
+        _ => { }
+    }
+}
+
+fn draw_pieces(pieces: &BoardState, white_pieces: &mut [Model2D; 6], black_pieces: &mut [Model2D; 6], shader: &Shader, flipped: bool) {
+    for (x, y, white, index) in pieces.sprites() {
+        let sprite = if white { &mut white_pieces[index] } else { &mut black_pieces[index] };
+        sprite.transform.translation = square_translation(x, y, flipped);
+        sprite.draw(shader);
+    }
+}
+
+fn host_on_pick(game: &mut HostGame, window: &Window) {
+    let cursor = window.get_cursor_pos();
+    
+    if cursor.0 <= 800.0 { 
+        let mut x: usize = (((cursor.0 as f32 * 8.0)) / 800.0).floor() as usize;
+        let mut y: usize = 7 - (((cursor.1 as f32 * 8.0)) / 800.0).floor() as usize;
+
+        if game.flipped {
+            x = 7 - x;
+            y = 7 - y;
+        }
+
+        reset_tiles(game);
+
+        if game.made_move.0 == usize::MAX {
+            let moves = game.chess.moves_from(x, y);
+            if moves.is_empty() {
+                return;
+            }
+
+            game.made_move.0 = x;
+            game.made_move.1 = y;
+
+            game.board[8*y + x].color =
+                game.board[8*y + x].color +
+                Vec4{ x: 0.4, y: 0.4, z: 0.0, w: 0.0 };
+
+            for m in moves {
+                game.board[8*m.end_y + m.end_x].color =
+                    game.board[8*m.end_y + m.end_x].color +
+                    Vec4{ x: 0.0, y: 0.4, z: 0.0, w: 0.0 };
+            }
+        } else {
+            let (sx, sy) = (game.made_move.0, game.made_move.1);
+            let moves: Vec<Move> = game.chess.moves_from(sx, sy).into_iter().filter(|m| (m.end_x, m.end_y) == (x, y)).collect();
+
+            if moves.is_empty() {
+                game.made_move = (usize::MAX, usize::MAX, usize::MAX, usize::MAX);
+                return;
+            }
+
+            game.made_move.2 = x;
+            game.made_move.3 = y;
+
+            if moves.iter().any(|m| m.promotion != Piece::None) {
+                game.promoting = true;
+            } else {
+                game.selected_prom = Piece::None;
+                host_play(game);
+            }
+        }
+    }
+}
+
+// The keys that change the game rather than what is shown of it.
+fn host_on_key(game: &mut HostGame, key: Key) {
+    match key {
+        Key::T => {
+            host_ask_takeback(game);
+        }
+
+        Key::Y => {
+            if game.takeback.asked {
+                take_back(game, false);
+            }
+        }
+
+        Key::N => {
+            if let Some(outbox) = &game.outbox {
+                game.takeback.refuse(outbox);
+                game.rematch.decline(outbox);
+            }
+        }
+
+        Key::R => {
+            host_offer_rematch(game);
+        }
+
+        _ => { }
+    }
+}
+
+fn host_play(game: &mut HostGame) {
+    let (sx, sy, ex, ey) = game.made_move;
+    let m = Move{ start_x: sx, start_y: sy, end_x: ex, end_y: ey, promotion: game.selected_prom };
+    let before = game.chess.board();
+
+    game.made_move = (usize::MAX, usize::MAX, usize::MAX, usize::MAX);
+    game.selected_prom = Piece::None;
+
+    if !game.chess.play(&m) {
+        return;
+    }
+
+    let sound = Sound::for_move(&before, &game.chess.board(), &m);
+    game.sounds.push(sound);
+    game.history.push(m.clone());
+    game.takeback.refused = false;
+    update_result(game);
+
+    if game.hot_seat {
+        game.host_white = !game.host_white;
+        game.flipped = game.flip && !game.host_white;
+        reset_tiles(game);
+        return;
+    }
+
+    send_state(game, m);
+    game.host_turn = false;
+    game.state = HostState::ClientPlaying;
+    reset_tiles(game);
+
+    wake_computer(game);
+}
+
+// Checks the server's board against our own position after `move_made`, or
+// against the unchanged position when the server only rejected our move. The
+// server's board is what gets shown either way, and our position follows it.
+fn verify_board(game: &mut ClientGame, board: Board, move_made: Option<&Move>) {
+    let mut expected = game.position.clone();
+    if let Some(m) = move_made {
+        expected.apply(m);
+    }
+
+    let squares = BoardState::from_protocol(expected.board).diff(&BoardState::from_protocol(board));
+
+    if !squares.is_empty() {
+        let names: Vec<String> = squares.iter().map(|&(x, y)| rules::square_name(x, y)).collect();
+        let message = format!("Differs on {}", names.join(" "));
+
+        eprintln!("Board from the host does not match our position. {}.", message);
+        game.desync = Some(message);
+    } else {
+        game.desync = None;
+    }
+
+    expected.board = board;
+    game.position = expected;
+}
+
+fn client_on_event(game: &mut ClientGame, event: ClientEvent) {
+    match event {
+        net::Event::Connected(des, outbox) => {
+            if ext::supports(&des.features, ext::CHAT) {
+                game.chat.connect(&outbox);
+            }
+
+            game.state = if game.client_white { ClientState::ClientPlaying } else { ClientState::HostPlaying };
+            game.host_turn = if game.client_white { false } else { true };
+            game.features = des.features;
+            game.moves = des.moves;
+
+            game.pieces = BoardState::from_protocol(des.board);
+            game.position = Position::from_board(des.board, true);
+            game.heartbeat.enabled = ext::supports(&game.features, ext::HEARTBEAT);
+            game.takeback.enabled = ext::supports(&game.features, ext::TAKEBACK);
+            game.rematch.enabled = ext::supports(&game.features, ext::REMATCH);
+            game.heartbeat.heard();
+            game.status = Status::Connected;
+            game.peer = outbox.peer().map(|a| a.to_string()).or(game.peer.take());
+            game.outbox = Some(outbox);
+        }
+
+        net::Event::Message(ServerToClient::State { board: b, moves, joever: j, move_made: m }) => {
+            game.heartbeat.heard();
+
+            // Catching up on the game so far is done quietly.
+            if game.replaying > 0 {
+                verify_board(game, b, Some(&m));
+                game.in_check = game.position.in_check();
+                game.pieces = BoardState::from_protocol(b);
+                game.game_end = j != Joever::Ongoing;
+                game.joever = j;
+                game.moves = moves;
+                game.played.push(m);
+                game.replaying -= 1;
+
+                if game.replaying == 0 {
+                    follow_position(game);
+                }
+                return;
+            }
+
+            time_answer(game);
+
+            let sound = if j != Joever::Ongoing { Sound::GameOver } else { Sound::for_move(&game.pieces.to_protocol(), &b, &m) };
+            game.sounds.push(sound);
+            verify_board(game, b, Some(&m));
+            game.in_check = game.position.in_check();
+            game.pieces = BoardState::from_protocol(b);
+
+            game.game_end = if j != Joever::Ongoing { true } else { false };
+            game.joever = j;
+            game.moves = moves;
+            game.played.push(m);
+            game.takeback.refused = false;
+            highlight_last_move(game);
+
+            if game.spectating {
+                return;
+            }
+
+            // Either the answer to our move or the host's own move.
+            game.host_turn = game.state == ClientState::WaitingForResponse;
+            game.state = if game.host_turn { ClientState::HostPlaying } else { ClientState::ClientPlaying };
+        }
+
+        net::Event::Message(ServerToClient::Error { board: b, moves, joever: _, message: _ }) => {
+            game.heartbeat.heard();
+            time_answer(game);
+
+            game.sounds.push(Sound::Illegal);
+            verify_board(game, b, None);
+            game.pieces = BoardState::from_protocol(b);
+            game.moves = moves;
+
+            if !game.spectating {
+                game.state = ClientState::ClientPlaying;
+            }
+        }
+
+        net::Event::Message(ServerToClient::Resigned { board: b, joever: j }) => {
+            game.heartbeat.heard();
+
+            if !game.game_end {
+                game.sounds.push(Sound::GameOver);
+            }
+
+            game.pieces = BoardState::from_protocol(b);
+            game.game_end = true;
+            game.joever = j;
+        }
+
+        net::Event::Message(_) => {
+            game.heartbeat.heard();
+        }
+
+        net::Event::Extension(Extension::TakenBack { plies, board, moves }) => {
+            game.heartbeat.heard();
+            game.takeback.done();
+
+            let keep = game.played.len().saturating_sub(plies);
+            game.played.truncate(keep);
+
+            // Played again from the start, like the host does.
+            game.position = Position::new();
+            for m in &game.played {
+                game.position.apply(m);
+            }
+
+            verify_board(game, board, None);
+            game.in_check = game.position.in_check();
+            game.pieces = BoardState::from_protocol(board);
+            game.moves = moves;
+            game.promoting = false;
+            game.selected_prom = Piece::None;
+            game.m_from = (usize::MAX, usize::MAX);
+            game.m_to = (usize::MAX, usize::MAX);
+
+            follow_position(game);
+        }
+
+        net::Event::Extension(Extension::NewGame { client_white, board, moves }) => {
+            game.heartbeat.heard();
+
+            if !game.spectating {
+                game.client_white = client_white;
+            }
+
+            game.position = Position::from_board(board, true);
+            game.pieces = BoardState::from_protocol(board);
+            game.moves = moves;
+            game.played.clear();
+            game.desync = None;
+            game.analysis = None;
+            game.game_end = false;
+            game.joever = Joever::Ongoing;
+            game.timed_out = false;
+            game.in_check = false;
+            game.promoting = false;
+            game.selected_prom = Piece::None;
+            game.m_from = (usize::MAX, usize::MAX);
+            game.m_to = (usize::MAX, usize::MAX);
+            game.takeback.done();
+            game.takeback.refused = false;
+            game.rematch.done();
+
+            follow_position(game);
+        }
+
+        // The host's word over our own reading of the board.
+        net::Event::Extension(Extension::Check { in_check }) => {
+            game.heartbeat.heard();
+            game.in_check = in_check;
+            highlight_last_move(game);
+        }
+
+        net::Event::Extension(Extension::RematchOffer) => {
+            game.heartbeat.heard();
+            game.rematch.asked = !game.spectating;
+        }
+
+        net::Event::Extension(Extension::RematchDeclined) => {
+            game.heartbeat.heard();
+
+            if game.rematch.sent {
+                game.rematch.sent = false;
+                game.rematch.declined = true;
+            }
+        }
+
+        net::Event::Extension(Extension::TakebackRequest) => {
+            game.heartbeat.heard();
+            game.takeback.asked = !game.spectating;
+        }
+
+        net::Event::Extension(Extension::TakebackAnswer { accepted: _ }) => {
+            game.heartbeat.heard();
+
+            // Accepting is answered with the game taken back.
+            if game.takeback.sent {
+                game.takeback.sent = false;
+                game.takeback.refused = true;
+            }
+        }
+
+        net::Event::Extension(Extension::Replay { states, spectator }) => {
+            game.heartbeat.heard();
+
+            // The game is played again from the start, so our position and
+            // the move list end up complete.
+            game.spectating = spectator;
+            game.replaying = states;
+            game.position = Position::new();
+            game.pieces = BoardState::from_protocol(rules::start_board());
+            game.played.clear();
+
+            if states == 0 {
+                follow_position(game);
+            }
+        }
+
+        net::Event::Extension(e) => {
+            game.heartbeat.heard();
+
+            if let Some(outbox) = &game.outbox {
+                game.heartbeat.on_extension(&e, outbox);
+                game.chat.on_extension(e, outbox);
+            }
+        }
+
+        net::Event::Closed(reason) => {
+            // The reader and the writer both report it, a failed connect
+            // only once.
+            if game.outbox.take().is_some() || game.state == ClientState::Pending {
+                eprintln!("Lost the connection to the host: {}", reason);
+                game.status = Status::Disconnected;
+            }
+        }
+    }
+}
+
+// The side to move follows from the moves played, after a replay or a
+// takeback whose turn it was before says nothing.
+fn follow_position(game: &mut ClientGame) {
+    highlight_last_move(game);
+
+    if game.spectating {
+        game.state = ClientState::HostPlaying;
+        return;
+    }
+
+    game.host_turn = game.position.white_to_move != game.client_white;
+    game.state = if game.host_turn { ClientState::HostPlaying } else { ClientState::ClientPlaying };
+}
+
+// Also shows the king in check, over the last move's squares.
+fn highlight_last_move(game: &mut ClientGame) {
+    for i in 0..64 {
+        game.board[i].color = game.board[i].default_color;
+    }
+
+    if let Some(m) = game.played.last() {
+        for (x, y) in [(m.start_x, m.start_y), (m.end_x, m.end_y)] {
+            game.board[y*8 + x].color = game.board[y*8 + x].color + Vec4{ x: 0.25, y: 0.25, z: 0.0, w: 0.0 };
+        }
+    }
+
+    if game.in_check {
+        tint_check(&mut game.board, &game.position.board, game.position.white_to_move);
+    }
+}
+
+fn client_abandon(game: &mut ClientGame) {
+    game.joever = match game.heartbeat.rule {
+        Abandon::Award => if game.client_white { Joever::White } else { Joever::Black },
+        Abandon::Adjourn => Joever::Indeterminate
+    };
+
+    game.game_end = true;
+    game.timed_out = true;
+    game.promoting = false;
+    game.sounds.push(Sound::GameOver);
+
+    if let Some(outbox) = game.outbox.take() {
+        outbox.close();
+    }
+
+    game.status = Status::Disconnected;
+    eprintln!("The host did not answer for {} seconds.", game.heartbeat.timeout.as_secs());
+}
+
+// Without heartbeats the time the host takes to answer a move is the best
+// measure of the round trip there is.
+fn time_answer(game: &mut ClientGame) {
+    if let Some(sent) = game.move_sent.take() {
+        if !game.heartbeat.enabled {
+            game.heartbeat.rtt = Some(sent.elapsed());
+        }
+    }
+}
+
+fn client_send_move(game: &mut ClientGame) {
+    let m = Move{ start_x: game.m_from.0, start_y: game.m_from.1, end_x: game.m_to.0, end_y: game.m_to.1, promotion: game.selected_prom };
+
+    if let Some(outbox) = &game.outbox {
+        let _ = outbox.send(&ClientToServer::Move(m));
+        game.move_sent = Some(std::time::Instant::now());
+    }
+
+    game.selected_prom = Piece::None;
+    game.m_from = (usize::MAX, usize::MAX);
+    game.m_to = (usize::MAX, usize::MAX);
+    game.state = ClientState::WaitingForResponse;
+}
+
+fn client_has_moved(game: &ClientGame) -> bool {
+    return game.played.len() >= if game.client_white { 1 } else { 2 };
+}
+
+fn client_on_key(game: &mut ClientGame, key: Key) {
+    let Some(outbox) = &game.outbox else {
+        return;
+    };
+
+    match key {
+        Key::T => {
+            if !game.game_end && !game.spectating && client_has_moved(game) {
+                game.takeback.request(outbox);
+            }
+        }
+
+        Key::Y => {
+            if game.takeback.asked {
+                let _ = outbox.send(&Extension::TakebackAnswer { accepted: true });
+                game.takeback.asked = false;
+            }
+        }
+
+        Key::N => {
+            game.takeback.refuse(outbox);
+            game.rematch.decline(outbox);
+        }
+
+        // Offering back accepts, the host starts the game.
+        Key::R => {
+            if game.game_end && !game.spectating {
+                game.rematch.offer(outbox);
+            }
+        }
+
+        _ => { }
+    }
+}
+
+fn client_on_pick(game: &mut ClientGame, window: &Window) {
+    let cursor = window.get_cursor_pos();
+    
+    if cursor.0 <= 800.0 { 
+        let x: usize = (((cursor.0 as f32 * 8.0)) / 800.0).floor() as usize;
+        let y: usize = 7 - (((cursor.1 as f32 * 8.0)) / 800.0).floor() as usize;
+
+        let movegen = game.features.contains(&Features::PossibleMoveGeneration);
+
+        if game.m_from == (usize::MAX, usize::MAX) {
+            if movegen {
+                let targets: Vec<(usize, usize)> = game.moves.iter()
+                    .filter(|m| (m.start_x, m.start_y) == (x, y))
+                    .map(|m| (m.end_x, m.end_y))
+                    .collect();
+
+                if targets.is_empty() {
+                    return;
+                }
+
+                for (a, b) in targets {
+                    game.board[b*8 + a].color = game.board[b*8 + a].color + Vec4{ x: 0.0, y: 0.4, z: 0.0, w: 0.0 };
+                }
+            }
+
+            game.board[y*8 + x].color = vec4(1.0, 0.0, 0.0, 1.0);
+
+            game.m_from = (x, y);
             return;
         }
 
         if game.m_to == (usize::MAX, usize::MAX) {
-            for i in 0..64 {
-                game.board[i].color = game.board[i].default_color;
+            highlight_last_move(game);
+
+            let from = game.m_from;
+            if movegen && !game.moves.iter().any(|m| (m.start_x, m.start_y, m.end_x, m.end_y) == (from.0, from.1, x, y)) {
+                game.m_from = (usize::MAX, usize::MAX);
+                return;
             }
 
             game.m_to = (x, y);
-            game.send_move = true;
+
+            let pawn = matches!(game.pieces.get(from.0, from.1), Piece::WhitePawn | Piece::BlackPawn);
+            if game.features.contains(&Features::Promotion) && pawn && (y == 0 || y == 7) {
+                game.promoting = true;
+            } else {
+                client_send_move(game);
+            }
             return;
         }
     }
 }
 
+fn render_analysis(shader: &Shader, analysis: &Option<Analysis>, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+    let grey = vec4(0.8, 0.8, 0.8, 1.0);
+
+    match analysis {
+        Some(analysis) => {
+            render_text(shader, analysis.label(), 800.0, 545.0, 0.4, vec4(1.0, 1.0, 1.0, 1.0), characters, char_quad);
+            render_text(shader, "Left, Right: step".to_string(), 800.0, 520.0, 0.3, grey, characters, char_quad);
+            render_text(shader, "Home, End: start, end".to_string(), 800.0, 500.0, 0.3, grey, characters, char_quad);
+            render_text(shader, "Click: try a move".to_string(), 800.0, 480.0, 0.3, grey, characters, char_quad);
+        }
+
+        None => {
+            render_text(shader, "Left: go over the game".to_string(), 800.0, 545.0, 0.3, grey, characters, char_quad);
+        }
+    }
+}
+
+fn render_takeback(shader: &Shader, takeback: &Takeback, can_ask: bool, opponent: &str, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+    let grey = vec4(0.8, 0.8, 0.8, 1.0);
+
+    if takeback.asked {
+        render_text(shader, format!("{} asks for a takeback.", opponent), 800.0, 535.0, 0.35, vec4(1.0, 0.9, 0.3, 1.0), characters, char_quad);
+        render_text(shader, "Y: accept, N: refuse".to_string(), 800.0, 515.0, 0.3, grey, characters, char_quad);
+    } else if takeback.sent {
+        render_text(shader, "Takeback asked for.".to_string(), 800.0, 535.0, 0.35, grey, characters, char_quad);
+    } else if takeback.refused {
+        render_text(shader, "Takeback refused.".to_string(), 800.0, 535.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), characters, char_quad);
+    } else if takeback.enabled && can_ask {
+        render_text(shader, "T: ask for a takeback".to_string(), 800.0, 535.0, 0.3, grey, characters, char_quad);
+    }
+}
+
+fn render_rematch(shader: &Shader, rematch: &Rematch, opponent: &str, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+    let grey = vec4(0.8, 0.8, 0.8, 1.0);
+
+    if rematch.asked {
+        render_text(shader, format!("{} offers a rematch.", opponent), 800.0, 450.0, 0.35, vec4(1.0, 0.9, 0.3, 1.0), characters, char_quad);
+        render_text(shader, "R: accept, N: decline".to_string(), 800.0, 430.0, 0.3, grey, characters, char_quad);
+    } else if rematch.sent {
+        render_text(shader, "Rematch offered.".to_string(), 800.0, 450.0, 0.35, grey, characters, char_quad);
+    } else if rematch.declined {
+        render_text(shader, "Rematch declined.".to_string(), 800.0, 450.0, 0.35, vec4(1.0, 0.3, 0.3, 1.0), characters, char_quad);
+    } else if rematch.enabled {
+        render_text(shader, "R: offer a rematch".to_string(), 800.0, 450.0, 0.3, grey, characters, char_quad);
+    }
+}
+
+// Splits `text` into lines of at most `width` characters, between words
+// where there are any.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+
+        while line.len() > width {
+            let rest = line.split_off(width);
+            lines.push(std::mem::replace(&mut line, rest));
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    return lines;
+}
+
+fn render_status(shader: &Shader, status: Status, heartbeat: &Heartbeat, peer: &Option<String>, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+    let status = if status == Status::Connected && heartbeat.lagging() { Status::Lagging } else { status };
+
+    let color = match status {
+        Status::Connecting => vec4(0.8, 0.8, 0.8, 1.0),
+        Status::Connected => vec4(0.5, 1.0, 0.5, 1.0),
+        Status::Lagging => vec4(1.0, 0.9, 0.3, 1.0),
+        Status::Disconnected => vec4(1.0, 0.3, 0.3, 1.0)
+    };
+
+    let text = match heartbeat.rtt {
+        Some(rtt) if status == Status::Connected || status == Status::Lagging => format!("{}, {} ms", status.label(), rtt.as_millis()),
+        _ => status.label().to_string()
+    };
+
+    render_text(shader, text, 810.0, 720.0, 0.35, color, characters, char_quad);
+
+    if let Some(peer) = peer {
+        render_text(shader, peer.clone(), 810.0, 700.0, 0.3, vec4(0.8, 0.8, 0.8, 1.0), characters, char_quad);
+    }
+}
+
 fn render_text(shader: &Shader, text: String, x: f32, y: f32, scale: f32, color: Vec4, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
     shader.set_vec4("color", color);
     unsafe {
@@ -1053,4 +2504,104 @@ fn orthographic_projection(right: f32, left: f32, top: f32, bottom: f32, near: f
     m[3][2] = -near / (far - near);
 
     return m;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected_client(client_white: bool) -> ClientGame {
+        let (outbox, _) = net::capture();
+        let mut game = ClientGame::new();
+        game.client_white = client_white;
+
+        let des = ServerToClientHandshake { features: vec![], board: rules::start_board(), moves: vec![], joever: Joever::Ongoing };
+        client_on_event(&mut game, net::Event::Connected(des, outbox));
+        return game;
+    }
+
+    #[test]
+    fn wrong_state_is_reported_in_full() {
+        let mut game = connected_client(true);
+        let m = rules::parse_move("e2e4", true).unwrap();
+
+        // The host says e2e4 was played but moved d2 and g1 as well.
+        let mut board = rules::start_board();
+        board[1][4] = Piece::None;
+        board[3][4] = Piece::WhitePawn;
+        board[1][3] = Piece::None;
+        board[0][6] = Piece::None;
+
+        game.state = ClientState::WaitingForResponse;
+        client_on_event(&mut game, net::Event::Message(ServerToClient::State { board: board, moves: vec![], joever: Joever::Ongoing, move_made: m }));
+
+        assert_eq!(game.desync.as_deref(), Some("Differs on g1 d2"));
+        assert_eq!(game.position.board, board);
+        assert!(game.state == ClientState::HostPlaying);
+    }
+
+    #[test]
+    fn spectators_keep_watching_after_an_error() {
+        let mut game = connected_client(false);
+        client_on_event(&mut game, net::Event::Extension(Extension::Replay { states: 0, spectator: true }));
+
+        let board = rules::start_board();
+        client_on_event(&mut game, net::Event::Message(ServerToClient::Error { board: board, moves: vec![], joever: Joever::Ongoing, message: "Not your turn".to_string() }));
+
+        assert!(game.spectating);
+        assert!(game.state != ClientState::ClientPlaying);
+        assert!(game.desync.is_none());
+    }
+
+    #[test]
+    fn host_keeps_its_colour() {
+        let mut game = HostGame::new(backend::from_name("builtin").unwrap());
+        game.host_white = false;
+
+        // Asking for black as well is refused, asking for white is not.
+        let (outbox, capture) = net::capture();
+        host_on_event(&mut game, 0, net::Event::Connected(ClientToServerHandshake { server_color: Color::White }, outbox), &[]);
+
+        let sent = capture.take();
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(serde_json::from_str(&sent[0]), Ok(ServerToClient::Error { .. })));
+        assert!(game.player.is_none());
+
+        let (outbox, capture) = net::capture();
+        host_on_event(&mut game, 1, net::Event::Connected(ClientToServerHandshake { server_color: Color::Black }, outbox), &[]);
+
+        assert!(serde_json::from_str::<ServerToClientHandshake>(&capture.take()[0]).is_ok());
+        assert_eq!(game.player, Some(1));
+        assert!(!game.host_white);
+    }
+
+    #[test]
+    fn live_players_keep_their_seat() {
+        let mut game = HostGame::new(backend::from_name("builtin").unwrap());
+        let seat = || ClientToServerHandshake { server_color: Color::White };
+
+        let (outbox, _capture) = net::capture();
+        host_on_event(&mut game, 0, net::Event::Connected(seat(), outbox), &[]);
+        assert_eq!(game.player, Some(0));
+
+        // The player is still there, asking for its colour is not enough.
+        let (outbox, _capture) = net::capture();
+        host_on_event(&mut game, 1, net::Event::Connected(seat(), outbox), &[]);
+        assert_eq!(game.player, Some(0));
+        assert_eq!(game.spectators.len(), 1);
+
+        // Once its connection is gone the seat is free to take back.
+        host_on_event(&mut game, 0, net::Event::Closed("gone".to_string()), &[]);
+        let (outbox, _capture) = net::capture();
+        host_on_event(&mut game, 2, net::Event::Connected(seat(), outbox), &[]);
+        assert_eq!(game.player, Some(2));
+    }
+
+    #[test]
+    fn long_text_is_wrapped_between_words() {
+        let text = "Differs on a1 b1 c1 d1 e1 f1 g1 h1 a2 b2";
+        let lines = wrap_text(text, 26);
+
+        assert_eq!(lines, vec!["Differs on a1 b1 c1 d1 e1", "f1 g1 h1 a2 b2"]);
+        assert!(wrap_text("abcdefghij", 4).iter().all(|l| l.len() <= 4));
+    }
+}
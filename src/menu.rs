@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use glfw::{Action, Key, MouseButton, WindowEvent};
+use glm::*;
+
+use crate::discovery::Browser;
+use crate::model::Model2D;
+use crate::shader::Shader;
+use crate::{render_text, Character};
+
+const MAX_ADDRESS: usize = 40;
+
+// Buttons as (centre x, centre y, width, height) in window pixels, measured
+// from the bottom left like the text.
+const HOST: (f32, f32, f32, f32) = (350.0, 540.0, 240.0, 50.0);
+const JOIN: (f32, f32, f32, f32) = (650.0, 540.0, 240.0, 50.0);
+const ADDRESS: (f32, f32, f32, f32) = (500.0, 440.0, 540.0, 44.0);
+const COLOUR: (f32, f32, f32, f32) = (500.0, 360.0, 240.0, 44.0);
+const START: (f32, f32, f32, f32) = (500.0, 270.0, 240.0, 50.0);
+
+// Games found on the network are listed from here downwards.
+const LIST_TOP: f32 = 170.0;
+const LIST_STEP: f32 = 25.0;
+const LIST_LEN: usize = 4;
+
+pub enum Choice {
+    // Whether to be found on the network and the colour the host asks for.
+    Host(bool, bool),
+    Join(String, bool)
+}
+
+// The screen shown when no address or mode was given on the command line.
+pub struct Menu {
+    joining: bool,
+    address: String,
+    white: bool,
+    lan: bool,
+    pub connecting: bool,
+    pub error: Option<String>,
+    browser: Option<Browser>,
+    buttons: Vec<((f32, f32, f32, f32), Model2D)>
+}
+
+fn button(rect: (f32, f32, f32, f32)) -> Model2D {
+    let mut model = Model2D::white_tile();
+    model.color = vec4(0.25, 0.25, 0.2, 1.0);
+    model.default_color = model.color;
+
+    // The tile projection is 100 pixels to a unit with the origin at (400, 400).
+    model.transform.translation = Vec2{ x: rect.0 / 100.0 - 4.0, y: rect.1 / 100.0 - 4.0 };
+    model.transform.scale = Vec2{ x: rect.2 / 100.0, y: rect.3 / 100.0 };
+    return model;
+}
+
+fn hit(rect: (f32, f32, f32, f32), cursor: (f64, f64)) -> bool {
+    let (x, y) = (cursor.0 as f32, 800.0 - cursor.1 as f32);
+    return (x - rect.0).abs() <= rect.2 / 2.0 && (y - rect.1).abs() <= rect.3 / 2.0;
+}
+
+impl Menu {
+    pub fn new(address: String, white: bool, lan: bool) -> Menu {
+        return Menu {
+            joining: true,
+            address: address,
+            white: white,
+            lan: lan,
+            connecting: false,
+            error: None,
+            browser: Browser::start().ok(),
+            buttons: [HOST, JOIN, ADDRESS, COLOUR, START].iter().map(|&r| (r, button(r))).collect()
+        };
+    }
+
+    pub fn update(&mut self) {
+        if let Some(browser) = &mut self.browser {
+            browser.update();
+        }
+    }
+
+    fn start(&mut self) -> Option<Choice> {
+        if !self.joining {
+            return Some(Choice::Host(self.lan, self.white));
+        }
+
+        if self.connecting {
+            return None;
+        }
+
+        if self.address.is_empty() {
+            self.error = Some("Enter the host's address.".to_string());
+            return None;
+        }
+
+        // Port 8384 is what hosts listen on.
+        let address = if self.address.contains(':') { self.address.clone() } else { format!("{}:8384", self.address) };
+
+        self.error = None;
+        self.connecting = true;
+        return Some(Choice::Join(address, self.white));
+    }
+
+    pub fn handle_event(&mut self, event: &WindowEvent, cursor: (f64, f64)) -> Option<Choice> {
+        match event {
+            WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                if hit(HOST, cursor) {
+                    self.joining = false;
+                } else if hit(JOIN, cursor) {
+                    self.joining = true;
+                } else if hit(COLOUR, cursor) {
+                    self.white = !self.white;
+                } else if hit(ADDRESS, cursor) && !self.joining {
+                    self.lan = !self.lan;
+                } else if hit(START, cursor) {
+                    return self.start();
+                } else if let Some(i) = self.listing_at(cursor) {
+                    // Take the colour the host did not ask for.
+                    let game = &self.browser.as_ref().unwrap().games[i];
+                    self.address = game.address.to_string();
+                    self.white = !game.host_white;
+                    return self.start();
+                }
+            }
+
+            WindowEvent::Key(Key::Enter, _, Action::Press, _) => {
+                return self.start();
+            }
+
+            WindowEvent::Key(Key::Tab, _, Action::Press, _) => {
+                self.joining = !self.joining;
+            }
+
+            WindowEvent::Char(c) if self.joining => {
+                if c.is_ascii() && !c.is_ascii_control() && !c.is_ascii_whitespace() && self.address.len() < MAX_ADDRESS {
+                    self.address.push(*c);
+                }
+            }
+
+            WindowEvent::Key(Key::Backspace, _, Action::Press | Action::Repeat, _) if self.joining => {
+                self.address.pop();
+            }
+
+            _ => { }
+        }
+
+        return None;
+    }
+
+    fn listing_at(&self, cursor: (f64, f64)) -> Option<usize> {
+        let browser = self.browser.as_ref()?;
+        let y = 800.0 - cursor.1 as f32;
+
+        // A line of text reaches about 20 pixels above its baseline.
+        if !self.joining || cursor.0 < 260.0 || cursor.0 > 740.0 || y > LIST_TOP + 20.0 {
+            return None;
+        }
+
+        let i = ((LIST_TOP + 20.0 - y) / LIST_STEP) as usize;
+        return if i < browser.games.len().min(LIST_LEN) { Some(i) } else { None };
+    }
+
+    pub fn draw_boxes(&mut self, shader: &Shader) {
+        let joining = self.joining;
+
+        for (rect, model) in self.buttons.iter_mut() {
+            let selected = (*rect == HOST && !joining) || (*rect == JOIN && joining);
+            model.color = if selected { vec4(0.45, 0.45, 0.3, 1.0) } else { model.default_color };
+            model.draw(shader);
+        }
+    }
+
+    pub fn draw_text(&self, shader: &Shader, characters: &HashMap<char, Character>, char_quad: &mut Model2D) {
+        let white = vec4(1.0, 1.0, 1.0, 1.0);
+        let grey = vec4(0.6, 0.6, 0.6, 1.0);
+
+        render_text(shader, "Chess".to_string(), 400.0, 660.0, 1.2, white, characters, char_quad);
+
+        render_text(shader, "Host game".to_string(), HOST.0 - 70.0, HOST.1 - 10.0, 0.5, white, characters, char_quad);
+        render_text(shader, "Join game".to_string(), JOIN.0 - 70.0, JOIN.1 - 10.0, 0.5, white, characters, char_quad);
+
+        if self.joining {
+            let address = if self.address.is_empty() { "Type the host's address".to_string() } else { format!("{}_", self.address) };
+            let color = if self.address.is_empty() { grey } else { white };
+            render_text(shader, address, ADDRESS.0 - ADDRESS.2 / 2.0 + 15.0, ADDRESS.1 - 10.0, 0.45, color, characters, char_quad);
+
+            match &self.browser {
+                Some(browser) if browser.games.is_empty() => {
+                    render_text(shader, "No games found on the network.".to_string(), 260.0, LIST_TOP, 0.35, grey, characters, char_quad);
+                }
+
+                Some(browser) => {
+                    for (i, game) in browser.games.iter().take(LIST_LEN).enumerate() {
+                        let colour = if game.host_white { "black" } else { "white" };
+                        let line = format!("{} at {}, play {}", game.name, game.address, colour);
+                        render_text(shader, line, 260.0, LIST_TOP - LIST_STEP * i as f32, 0.35, white, characters, char_quad);
+                    }
+                }
+
+                None => {
+                    render_text(shader, "Can not look for games on the network.".to_string(), 260.0, LIST_TOP, 0.35, grey, characters, char_quad);
+                }
+            }
+        } else {
+            let lan = if self.lan { "Visible on the network" } else { "Only on this machine" };
+            render_text(shader, lan.to_string(), ADDRESS.0 - ADDRESS.2 / 2.0 + 15.0, ADDRESS.1 - 10.0, 0.45, white, characters, char_quad);
+        }
+
+        let colour = if self.white { "Play white" } else { "Play black" };
+        render_text(shader, colour.to_string(), COLOUR.0 - 80.0, COLOUR.1 - 10.0, 0.45, white, characters, char_quad);
+
+        let start = if !self.joining { "Start" } else if self.connecting { "Connecting..." } else { "Connect" };
+        render_text(shader, start.to_string(), START.0 - 90.0, START.1 - 10.0, 0.5, white, characters, char_quad);
+
+        if let Some(error) = &self.error {
+            render_text(shader, error.clone(), 260.0, 210.0, 0.4, vec4(1.0, 0.3, 0.3, 1.0), characters, char_quad);
+        }
+
+        render_text(shader, "Tab: host or join, Enter: start, Esc: quit".to_string(), 260.0, 60.0, 0.35, grey, characters, char_quad);
+    }
+}
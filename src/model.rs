@@ -48,23 +48,31 @@ pub struct Model2D {
     pub default_color: Vec4
 }
 
+// Without a window there is no GL to load. Models are then kept without any
+// buffers, so the game can be run headless, e.g. to replay a recording.
+fn headless() -> bool {
+    return !gl::GenVertexArrays::is_loaded();
+}
+
 #[allow(dead_code)]
 impl Model2D {
     fn tile(vertices: Vec<Vec2>, default_color: Vec4) -> Model2D {
         let mut vao: u32 = 0;
         let mut vbo: u32 = 0;
         
-        unsafe {
-        gl::GenVertexArrays(1, &mut vao);
-        gl::GenBuffers(1, &mut vbo);
-        gl::BindVertexArray(vao);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * std::mem::size_of::<Vec2>()) as isize, vertices.as_ptr().cast(), gl::STATIC_DRAW);
-        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, std::mem::size_of::<Vec2>().try_into().unwrap(), 0 as *const _);
-        gl::EnableVertexAttribArray(0);
-
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        gl::BindVertexArray(0);
+        if !headless() {
+            unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * std::mem::size_of::<Vec2>()) as isize, vertices.as_ptr().cast(), gl::STATIC_DRAW);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, std::mem::size_of::<Vec2>().try_into().unwrap(), 0 as *const _);
+            gl::EnableVertexAttribArray(0);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+            }
         }
 
         return Model2D {
@@ -83,19 +91,21 @@ impl Model2D {
         let mut vao: u32 = 0;
         let mut vbo: u32 = 0;
         
-        unsafe {
-        gl::GenVertexArrays(1, &mut vao);
-        gl::GenBuffers(1, &mut vbo);
-        gl::BindVertexArray(vao);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * 2 * std::mem::size_of::<Vec2>()) as isize, vertices.as_ptr().cast(), gl::STATIC_DRAW);
-        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, (std::mem::size_of::<Vec2>() * 2).try_into().unwrap(), 0 as *const _);
-        gl::EnableVertexAttribArray(0);
-        gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, (std::mem::size_of::<Vec2>() * 2).try_into().unwrap(), 8 as *const _);
-        gl::EnableVertexAttribArray(1);
-
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        gl::BindVertexArray(0);
+        if !headless() {
+            unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * 2 * std::mem::size_of::<Vec2>()) as isize, vertices.as_ptr().cast(), gl::STATIC_DRAW);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, (std::mem::size_of::<Vec2>() * 2).try_into().unwrap(), 0 as *const _);
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, (std::mem::size_of::<Vec2>() * 2).try_into().unwrap(), 8 as *const _);
+            gl::EnableVertexAttribArray(1);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+            }
         }
 
         return Model2D {
@@ -114,17 +124,19 @@ impl Model2D {
         let mut vao: u32 = 0;
         let mut vbo: u32 = 0;
         
-        unsafe {
-        gl::GenVertexArrays(1, &mut vao);
-        gl::GenBuffers(1, &mut vbo);
-        gl::BindVertexArray(vao);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferData(gl::ARRAY_BUFFER, (6 * 4 * std::mem::size_of::<f32>()) as isize, 0 as *const _, gl::DYNAMIC_DRAW);
-        gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, (std::mem::size_of::<f32>() * 4).try_into().unwrap(), 0 as *const _);
-        gl::EnableVertexAttribArray(0);
-
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        gl::BindVertexArray(0);
+        if !headless() {
+            unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (6 * 4 * std::mem::size_of::<f32>()) as isize, 0 as *const _, gl::DYNAMIC_DRAW);
+            gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, (std::mem::size_of::<f32>() * 4).try_into().unwrap(), 0 as *const _);
+            gl::EnableVertexAttribArray(0);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+            }
         }
 
         return Model2D {
@@ -217,6 +229,10 @@ impl Model2D {
 
 impl Drop for Model2D {
     fn drop(&mut self) {
+        if self.vao == 0 {
+            return;
+        }
+
         unsafe {
         gl::DeleteBuffers(1, &self.vbo);
         gl::DeleteVertexArrays(1, &self.vao);
@@ -0,0 +1,153 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::ext::{Extension, Incoming};
+use crate::wire::{Reader, Writer};
+
+// The render loop owns the game. Whatever happens on the connection reaches it
+// as an event on a channel, and whatever it sends is queued for a writer
+// thread, so it never waits on the socket and nothing has to be locked.
+pub enum Event<H, T> {
+    Connected(H, Outbox),
+    Message(T),
+    Extension(Extension),
+    Closed(String)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Connecting,
+    Connected,
+    Lagging,
+    Disconnected
+}
+
+enum Outgoing {
+    Json(String),
+    Framed(bool),
+    Close
+}
+
+#[derive(Clone)]
+pub struct Outbox {
+    tx: Sender<Outgoing>,
+    peer: Option<SocketAddr>
+}
+
+// The other end of an outbox that has no connection behind it.
+pub struct Capture {
+    rx: Receiver<Outgoing>
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        return match self {
+            Status::Connecting => "Connecting",
+            Status::Connected => "Connected",
+            Status::Lagging => "Lagging",
+            Status::Disconnected => "Disconnected"
+        };
+    }
+}
+
+impl Outbox {
+    pub fn peer(&self) -> Option<SocketAddr> {
+        return self.peer;
+    }
+
+    pub fn send<T: Serialize>(&self, message: &T) -> io::Result<()> {
+        let text = serde_json::to_string(message)?;
+
+        return self.tx.send(Outgoing::Json(text))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"));
+    }
+
+    // Applies to everything sent after it.
+    pub fn set_framed(&self, framed: bool) {
+        let _ = self.tx.send(Outgoing::Framed(framed));
+    }
+
+    // Hangs up once everything sent before it is written.
+    pub fn close(&self) {
+        let _ = self.tx.send(Outgoing::Close);
+    }
+}
+
+impl Capture {
+    // The messages sent since the last call, as they would have been written.
+    pub fn take(&self) -> Vec<String> {
+        return self.rx.try_iter().filter_map(|outgoing| match outgoing {
+            Outgoing::Json(text) => Some(text),
+            _ => None
+        }).collect();
+    }
+}
+
+// Lets the game run without a connection, e.g. to replay a recording, while
+// whatever it sends can still be looked at.
+pub fn capture() -> (Outbox, Capture) {
+    let (tx, rx) = mpsc::channel();
+    return (Outbox { tx: tx, peer: None }, Capture { rx: rx });
+}
+
+// Takes over a connection once the handshake is done. `handshake` is what the
+// other side sent and arrives with the outbox before any message does.
+pub fn spawn<H, T>(mut reader: Reader, writer: Writer, handshake: H, events: Sender<Event<H, T>>)
+where
+    H: Send + 'static,
+    T: DeserializeOwned + Send + 'static
+{
+    let (tx, rx) = mpsc::channel();
+    let writer_events = events.clone();
+    let peer = writer.peer_addr().ok();
+
+    thread::spawn(move || {
+        for outgoing in rx {
+            let written = match outgoing {
+                Outgoing::Json(text) => writer.write_json(text),
+
+                Outgoing::Framed(framed) => {
+                    writer.set_framed(framed);
+                    Ok(())
+                }
+
+                Outgoing::Close => {
+                    writer.shutdown();
+                    break;
+                }
+            };
+
+            if let Err(e) = written {
+                let _ = writer_events.send(Event::Closed(e.to_string()));
+                break;
+            }
+        }
+    });
+
+    if events.send(Event::Connected(handshake, Outbox { tx: tx, peer: peer })).is_err() {
+        return;
+    }
+
+    thread::spawn(move || {
+        loop {
+            let event = match reader.read::<Incoming<T>>() {
+                Ok(Incoming::Message(m)) => Event::Message(m),
+                Ok(Incoming::Extension(e)) => Event::Extension(e),
+
+                Err(e) => {
+                    let _ = events.send(Event::Closed(e.to_string()));
+                    break;
+                }
+            };
+
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// A recording is one JSON object per line, in the order the messages were
+// sent or read. Handshakes and password challenges are included, so a
+// session can be followed from the very first byte.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    // Milliseconds since the Unix epoch.
+    pub time: u64,
+    pub direction: Direction,
+    // Who was on the other end, a host records every connection it took.
+    pub peer: Option<String>,
+    pub message: Value
+}
+
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+    peer: Option<String>
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Recorder> {
+        return Ok(Recorder { file: Arc::new(Mutex::new(File::create(path)?)), peer: None });
+    }
+
+    // The same file, with entries marked as coming from `peer`.
+    pub fn connection(&self, peer: Option<SocketAddr>) -> Recorder {
+        return Recorder { file: self.file.clone(), peer: peer.map(|p| p.to_string()) };
+    }
+
+    pub fn sent(&self, text: &str) {
+        // Whatever is written was serialized by us and parses.
+        if let Ok(message) = serde_json::from_str(text) {
+            self.write(Direction::Sent, message);
+        }
+    }
+
+    pub fn received(&self, message: &Value) {
+        self.write(Direction::Received, message.clone());
+    }
+
+    fn write(&self, direction: Direction, message: Value) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let entry = Entry { time: time, direction: direction, peer: self.peer.clone(), message: message };
+
+        // A recording that can not be written should not end the game.
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let mut file = self.file.lock().unwrap();
+            if let Err(e) = writeln!(file, "{}", line) {
+                eprintln!("Could not record a message: {}", e);
+            }
+        }
+    }
+}
+
+pub fn load(path: &str) -> io::Result<Vec<Entry>> {
+    let mut entries = vec![];
+
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        entries.push(serde_json::from_str(&line)?);
+    }
+
+    return Ok(entries);
+}
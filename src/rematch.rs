@@ -0,0 +1,43 @@
+use crate::ext::Extension;
+use crate::net::Outbox;
+
+// Once a game is over either side may offer another one over the same
+// connection, if both agreed on the `Rematch` extension. Offering back is
+// accepting. The host then starts the new game with the colours swapped and
+// sends its board like a handshake would.
+pub struct Rematch {
+    pub enabled: bool,
+    pub sent: bool,
+    pub asked: bool,
+    pub declined: bool
+}
+
+impl Rematch {
+    pub fn new() -> Rematch {
+        return Rematch { enabled: false, sent: false, asked: false, declined: false };
+    }
+
+    pub fn offer(&mut self, outbox: &Outbox) {
+        if !self.enabled || self.sent {
+            return;
+        }
+
+        if outbox.send(&Extension::RematchOffer).is_ok() {
+            self.sent = true;
+            self.declined = false;
+        }
+    }
+
+    pub fn decline(&mut self, outbox: &Outbox) {
+        if self.asked {
+            let _ = outbox.send(&Extension::RematchDeclined);
+            self.asked = false;
+        }
+    }
+
+    pub fn done(&mut self) {
+        self.sent = false;
+        self.asked = false;
+        self.declined = false;
+    }
+}
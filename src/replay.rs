@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::io;
+
+use chess_network_protocol::*;
+use glfw::Key;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::backend;
+use crate::ext::{Extension, Incoming};
+use crate::net::{self, Capture, Outbox};
+use crate::record::{self, Direction, Entry};
+use crate::{client_on_event, client_on_key, client_send_move, host_abandon, host_on_event, host_on_key, host_play};
+use crate::{ClientGame, ClientState, HostGame, HostState};
+
+// Runs a recorded session through the game's own event handlers, without a
+// window or a connection. What was read becomes events, and what the player
+// did is done again with the same keys and moves. Everything that was sent
+// has to be sent again, in the same order. Whatever disagrees is printed
+// with the line it is on, the same file always gives the same output.
+struct Replay {
+    problems: usize,
+    moves: usize,
+    capture: Capture,
+    // Sent by the handlers, still to be matched with the recording.
+    sent: VecDeque<Value>
+}
+
+impl Replay {
+    fn problem(&mut self, line: usize, text: String) {
+        println!("Line {}: {}", line, text);
+        self.problems += 1;
+    }
+
+    fn collect(&mut self) {
+        for text in self.capture.take() {
+            // Whatever is sent was serialized by the game and parses.
+            if let Ok(message) = serde_json::from_str(&text) {
+                self.sent.push_back(message);
+            }
+        }
+    }
+
+    // The recording says `message` was sent next.
+    fn expect(&mut self, line: usize, message: &Value) {
+        if parse::<Incoming<ServerToClient>>(message).is_some_and(|m| matches!(m, Incoming::Message(ServerToClient::State { .. }))) {
+            self.moves += 1;
+        }
+
+        match self.sent.pop_front() {
+            Some(sent) if sent == *message => { }
+            Some(sent) => { self.problem(line, format!("sent {}, the replay sends {}", message, sent)); }
+            None => { self.problem(line, format!("sent {}, the replay sends nothing", message)); }
+        }
+    }
+
+    fn finish(&mut self, line: usize) {
+        while let Some(sent) = self.sent.pop_front() {
+            self.problem(line, format!("the replay also sends {}", sent));
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(message: &Value) -> Option<T> {
+    return serde_json::from_value(message.clone()).ok();
+}
+
+// Sent by the connection or on a timer rather than by the game, or typed
+// into the chat. None of it is sent again.
+fn not_replayed(message: &Value) -> bool {
+    return matches!(parse::<Extension>(message), Some(Extension::Ping { .. } | Extension::Chat { .. } | Extension::Challenge { .. } | Extension::Response { .. }));
+}
+
+// Returns how many problems were found.
+pub fn run(path: &str, engine: &str) -> io::Result<usize> {
+    let entries = record::load(path)?;
+
+    // A host records everyone watching in the same file, only the first
+    // connection is followed. Lines are numbered from one like an editor does.
+    let peer = entries.first().and_then(|e| e.peer.clone());
+    let session: Vec<(usize, &Entry)> = entries.iter().enumerate()
+        .filter(|(_, e)| e.peer == peer)
+        .map(|(i, e)| (i + 1, e))
+        .collect();
+
+    // The handshake we read tells which side did the recording.
+    let host = session.iter()
+        .filter(|(_, e)| e.direction == Direction::Received)
+        .find_map(|(_, e)| {
+            if parse::<ClientToServerHandshake>(&e.message).is_some() {
+                return Some(true);
+            }
+
+            return parse::<ServerToClientHandshake>(&e.message).map(|_| false);
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no handshake in the recording"))?;
+
+    let (outbox, capture) = net::capture();
+    let mut replay = Replay { problems: 0, moves: 0, capture: capture, sent: VecDeque::new() };
+
+    if host {
+        let chess = backend::from_name(engine).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown engine \"{}\"", engine)))?;
+        replay_host(&session, HostGame::new(chess), outbox, &mut replay);
+    } else {
+        replay_client(&session, ClientGame::new(), outbox, &mut replay);
+    }
+
+    replay.finish(session.last().map(|&(line, _)| line).unwrap_or(0));
+
+    println!("Replayed {} messages as the {}, {} moves, {} problems.", session.len(), if host { "host" } else { "client" }, replay.moves, replay.problems);
+    return Ok(replay.problems);
+}
+
+fn replay_host(session: &[(usize, &Entry)], mut game: HostGame, outbox: Outbox, replay: &mut Replay) {
+    // The features we offered are in the handshake we sent.
+    let features = session.iter()
+        .filter(|(_, e)| e.direction == Direction::Sent)
+        .find_map(|(_, e)| parse::<ServerToClientHandshake>(&e.message))
+        .map(|h| h.features)
+        .unwrap_or_default();
+
+    for &(line, entry) in session {
+        if entry.direction == Direction::Sent {
+            if not_replayed(&entry.message) {
+                continue;
+            }
+
+            if replay.sent.is_empty() {
+                host_act(&mut game, &entry.message);
+                replay.collect();
+            }
+
+            replay.expect(line, &entry.message);
+            continue;
+        }
+
+        let event = if let Some(des) = parse::<ClientToServerHandshake>(&entry.message) {
+            net::Event::Connected(des, outbox.clone())
+        } else {
+            match parse::<Incoming<ClientToServer>>(&entry.message) {
+                Some(Incoming::Extension(Extension::Response { .. })) => { continue; }
+                Some(Incoming::Extension(e)) => net::Event::Extension(e),
+                Some(Incoming::Message(m)) => net::Event::Message(m),
+
+                None => {
+                    replay.problem(line, format!("read {}, which is no message", entry.message));
+                    continue;
+                }
+            }
+        };
+
+        host_on_event(&mut game, 0, event, &features);
+        replay.collect();
+    }
+}
+
+// Does what the host player must have done for `message` to be sent.
+fn host_act(game: &mut HostGame, message: &Value) {
+    match parse::<Incoming<ServerToClient>>(message) {
+        Some(Incoming::Message(ServerToClient::State { board: _, moves: _, joever: _, move_made: m })) => {
+            if !game.game_end && game.state == HostState::HostPlaying && game.client_connected {
+                game.made_move = (m.start_x, m.start_y, m.end_x, m.end_y);
+                game.selected_prom = m.promotion;
+                host_play(game);
+            }
+        }
+
+        // Nothing is resigned from the window, the client timed out.
+        Some(Incoming::Message(ServerToClient::Resigned { board: _, joever: _ })) => {
+            if !game.game_end {
+                host_abandon(game);
+            }
+        }
+
+        Some(Incoming::Extension(Extension::TakebackRequest)) => { host_on_key(game, Key::T); }
+        Some(Incoming::Extension(Extension::TakenBack { plies: _, board: _, moves: _ })) => { host_on_key(game, Key::Y); }
+        Some(Incoming::Extension(Extension::TakebackAnswer { accepted: false } | Extension::RematchDeclined)) => { host_on_key(game, Key::N); }
+        Some(Incoming::Extension(Extension::RematchOffer | Extension::NewGame { client_white: _, board: _, moves: _ })) => { host_on_key(game, Key::R); }
+
+        _ => { }
+    }
+}
+
+fn replay_client(session: &[(usize, &Entry)], mut game: ClientGame, outbox: Outbox, replay: &mut Replay) {
+    let mut connected = false;
+
+    for &(line, entry) in session {
+        if entry.direction == Direction::Sent {
+            // The handshake, the password and the acknowledgement are
+            // written before the game takes over the connection.
+            if !connected {
+                if let Some(handshake) = parse::<ClientToServerHandshake>(&entry.message) {
+                    game.client_white = handshake.server_color == Color::Black;
+                }
+                continue;
+            }
+
+            if let Some(Extension::Features(_)) = parse(&entry.message) {
+                continue;
+            }
+
+            if not_replayed(&entry.message) {
+                continue;
+            }
+
+            if replay.sent.is_empty() {
+                client_act(&mut game, &entry.message);
+                replay.collect();
+            }
+
+            replay.expect(line, &entry.message);
+            continue;
+        }
+
+        let event = if !connected {
+            let Some(des) = parse::<ServerToClientHandshake>(&entry.message) else {
+                continue;
+            };
+
+            connected = true;
+            net::Event::Connected(des, outbox.clone())
+        } else {
+            match parse::<Incoming<ServerToClient>>(&entry.message) {
+                Some(Incoming::Extension(e)) => net::Event::Extension(e),
+                Some(Incoming::Message(m)) => net::Event::Message(m),
+
+                None => {
+                    replay.problem(line, format!("read {}, which is no message", entry.message));
+                    continue;
+                }
+            }
+        };
+
+        if let net::Event::Message(ServerToClient::State { board: _, moves: _, joever: _, move_made: _ }) = &event {
+            replay.moves += 1;
+        }
+
+        client_on_event(&mut game, event);
+        replay.collect();
+
+        if let Some(desync) = game.desync.take() {
+            replay.problem(line, format!("the board from the host does not match our position. {}", desync));
+        }
+    }
+}
+
+// Does what the client player must have done for `message` to be sent.
+fn client_act(game: &mut ClientGame, message: &Value) {
+    match parse::<Incoming<ClientToServer>>(message) {
+        Some(Incoming::Message(ClientToServer::Move(m))) => {
+            if !game.game_end && game.state == ClientState::ClientPlaying {
+                game.m_from = (m.start_x, m.start_y);
+                game.m_to = (m.end_x, m.end_y);
+                game.selected_prom = m.promotion;
+                client_send_move(game);
+            }
+        }
+
+        Some(Incoming::Extension(Extension::TakebackRequest)) => { client_on_key(game, Key::T); }
+        Some(Incoming::Extension(Extension::TakebackAnswer { accepted: true })) => { client_on_key(game, Key::Y); }
+        Some(Incoming::Extension(Extension::TakebackAnswer { accepted: false } | Extension::RematchDeclined)) => { client_on_key(game, Key::N); }
+        Some(Incoming::Extension(Extension::RematchOffer)) => { client_on_key(game, Key::R); }
+
+        _ => { }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // Both ends of the same short game: a few moves, a takeback the host
+    // agreed to, a move the host refused, a heartbeat and a chat message.
+    fn recording(side: &str) -> String {
+        return format!("{}/tests/recordings/{}.jsonl", env!("CARGO_MANIFEST_DIR"), side);
+    }
+
+    #[test]
+    fn host_recording_replays() {
+        assert_eq!(run(&recording("host"), "builtin").unwrap(), 0);
+    }
+
+    #[test]
+    fn client_recording_replays() {
+        assert_eq!(run(&recording("client"), "builtin").unwrap(), 0);
+    }
+
+    #[test]
+    fn differences_are_found() {
+        let text = fs::read_to_string(recording("host")).unwrap();
+        let changed = text.replacen("{\"Check\":{\"in_check\":false}}", "{\"Check\":{\"in_check\":true}}", 1);
+
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        fs::write(&path, changed).unwrap();
+
+        assert_eq!(run(path.to_str().unwrap(), "builtin").unwrap(), 1);
+    }
+}
@@ -0,0 +1,625 @@
+use chess_network_protocol::{Joever, Move, Piece};
+
+pub type Board = [[Piece; 8]; 8];
+
+const KNIGHT_OFFSETS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_OFFSETS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const STRAIGHT: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const DIAGONAL: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+pub fn is_white(piece: Piece) -> bool {
+    return matches!(piece, Piece::WhitePawn | Piece::WhiteKnight | Piece::WhiteBishop | Piece::WhiteRook | Piece::WhiteQueen | Piece::WhiteKing);
+}
+
+fn at(board: &Board, x: i32, y: i32) -> Option<Piece> {
+    if x < 0 || x > 7 || y < 0 || y > 7 {
+        return None;
+    }
+
+    return Some(board[y as usize][x as usize]);
+}
+
+pub fn king_square(board: &Board, white: bool) -> Option<(usize, usize)> {
+    let king = if white { Piece::WhiteKing } else { Piece::BlackKing };
+
+    for y in 0..8 {
+        for x in 0..8 {
+            if board[y][x] == king {
+                return Some((x, y));
+            }
+        }
+    }
+
+    return None;
+}
+
+// White moves towards increasing y, the same orientation the board is drawn in.
+pub fn is_attacked(board: &Board, x: usize, y: usize, by_white: bool) -> bool {
+    let (x, y) = (x as i32, y as i32);
+
+    let (pawn, knight, bishop, rook, queen, king) = if by_white {
+        (Piece::WhitePawn, Piece::WhiteKnight, Piece::WhiteBishop, Piece::WhiteRook, Piece::WhiteQueen, Piece::WhiteKing)
+    } else {
+        (Piece::BlackPawn, Piece::BlackKnight, Piece::BlackBishop, Piece::BlackRook, Piece::BlackQueen, Piece::BlackKing)
+    };
+
+    let pawn_dir = if by_white { -1 } else { 1 };
+    if at(board, x - 1, y + pawn_dir) == Some(pawn) || at(board, x + 1, y + pawn_dir) == Some(pawn) {
+        return true;
+    }
+
+    for (dx, dy) in KNIGHT_OFFSETS {
+        if at(board, x + dx, y + dy) == Some(knight) {
+            return true;
+        }
+    }
+
+    for (dx, dy) in KING_OFFSETS {
+        if at(board, x + dx, y + dy) == Some(king) {
+            return true;
+        }
+    }
+
+    for (dirs, slider) in [(STRAIGHT, rook), (DIAGONAL, bishop)] {
+        for (dx, dy) in dirs {
+            let (mut cx, mut cy) = (x + dx, y + dy);
+            while let Some(p) = at(board, cx, cy) {
+                if p == slider || p == queen {
+                    return true;
+                }
+
+                if p != Piece::None {
+                    break;
+                }
+
+                cx += dx;
+                cy += dy;
+            }
+        }
+    }
+
+    return false;
+}
+
+pub fn in_check(board: &Board, white: bool) -> bool {
+    return match king_square(board, white) {
+        Some((x, y)) => is_attacked(board, x, y, !white),
+        None => false
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King
+}
+
+pub fn kind(piece: Piece) -> Option<Kind> {
+    return match piece {
+        Piece::WhitePawn | Piece::BlackPawn => Some(Kind::Pawn),
+        Piece::WhiteKnight | Piece::BlackKnight => Some(Kind::Knight),
+        Piece::WhiteBishop | Piece::BlackBishop => Some(Kind::Bishop),
+        Piece::WhiteRook | Piece::BlackRook => Some(Kind::Rook),
+        Piece::WhiteQueen | Piece::BlackQueen => Some(Kind::Queen),
+        Piece::WhiteKing | Piece::BlackKing => Some(Kind::King),
+        Piece::None => None
+    };
+}
+
+pub fn make(kind: Kind, white: bool) -> Piece {
+    return match (kind, white) {
+        (Kind::Pawn, true) => Piece::WhitePawn,
+        (Kind::Knight, true) => Piece::WhiteKnight,
+        (Kind::Bishop, true) => Piece::WhiteBishop,
+        (Kind::Rook, true) => Piece::WhiteRook,
+        (Kind::Queen, true) => Piece::WhiteQueen,
+        (Kind::King, true) => Piece::WhiteKing,
+        (Kind::Pawn, false) => Piece::BlackPawn,
+        (Kind::Knight, false) => Piece::BlackKnight,
+        (Kind::Bishop, false) => Piece::BlackBishop,
+        (Kind::Rook, false) => Piece::BlackRook,
+        (Kind::Queen, false) => Piece::BlackQueen,
+        (Kind::King, false) => Piece::BlackKing
+    };
+}
+
+pub fn start_board() -> Board {
+    let back = [Kind::Rook, Kind::Knight, Kind::Bishop, Kind::Queen, Kind::King, Kind::Bishop, Kind::Knight, Kind::Rook];
+    let mut board = [[Piece::None; 8]; 8];
+
+    for x in 0..8 {
+        board[0][x] = make(back[x], true);
+        board[1][x] = Piece::WhitePawn;
+        board[6][x] = Piece::BlackPawn;
+        board[7][x] = make(back[x], false);
+    }
+
+    return board;
+}
+
+// A complete position, enough to generate legal moves without an external
+// engine. Castling rights are ordered white king side, white queen side,
+// black king side, black queen side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub board: Board,
+    pub white_to_move: bool,
+    pub castling: [bool; 4],
+    pub en_passant: Option<(usize, usize)>,
+    pub halfmove_clock: u32,
+    pub fullmove: u32
+}
+
+impl Position {
+    pub fn new() -> Position {
+        return Position::from_board(start_board(), true);
+    }
+
+    // The protocol only sends the board, so castling rights are assumed as
+    // long as king and rook are still on their starting squares.
+    pub fn from_board(board: Board, white_to_move: bool) -> Position {
+        let castling = [
+            board[0][4] == Piece::WhiteKing && board[0][7] == Piece::WhiteRook,
+            board[0][4] == Piece::WhiteKing && board[0][0] == Piece::WhiteRook,
+            board[7][4] == Piece::BlackKing && board[7][7] == Piece::BlackRook,
+            board[7][4] == Piece::BlackKing && board[7][0] == Piece::BlackRook
+        ];
+
+        return Position {
+            board: board,
+            white_to_move: white_to_move,
+            castling: castling,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove: 1
+        };
+    }
+
+    pub fn in_check(&self) -> bool {
+        return in_check(&self.board, self.white_to_move);
+    }
+
+    fn push_pawn_move(moves: &mut Vec<Move>, from: (usize, usize), to: (usize, usize), white: bool) {
+        if to.1 == 0 || to.1 == 7 {
+            for k in [Kind::Queen, Kind::Rook, Kind::Bishop, Kind::Knight] {
+                moves.push(Move{ start_x: from.0, start_y: from.1, end_x: to.0, end_y: to.1, promotion: make(k, white) });
+            }
+        } else {
+            moves.push(Move{ start_x: from.0, start_y: from.1, end_x: to.0, end_y: to.1, promotion: Piece::None });
+        }
+    }
+
+    fn pseudo_moves(&self) -> Vec<Move> {
+        let white = self.white_to_move;
+        let board = &self.board;
+        let mut moves = vec![];
+
+        let push = |moves: &mut Vec<Move>, x: usize, y: usize, tx: i32, ty: i32| {
+            moves.push(Move{ start_x: x, start_y: y, end_x: tx as usize, end_y: ty as usize, promotion: Piece::None });
+        };
+
+        for y in 0..8usize {
+            for x in 0..8usize {
+                let p = board[y][x];
+                if p == Piece::None || is_white(p) != white {
+                    continue;
+                }
+
+                let (xi, yi) = (x as i32, y as i32);
+                let free = |tx: i32, ty: i32| at(board, tx, ty) == Some(Piece::None);
+                let enemy = |tx: i32, ty: i32| matches!(at(board, tx, ty), Some(t) if t != Piece::None && is_white(t) != white);
+
+                match kind(p) {
+                    Some(Kind::Pawn) => {
+                        let dir = if white { 1 } else { -1 };
+                        let home = if white { 1 } else { 6 };
+
+                        if free(xi, yi + dir) {
+                            Self::push_pawn_move(&mut moves, (x, y), (x, (yi + dir) as usize), white);
+
+                            if yi == home && free(xi, yi + 2 * dir) {
+                                push(&mut moves, x, y, xi, yi + 2 * dir);
+                            }
+                        }
+
+                        for dx in [-1, 1] {
+                            let (tx, ty) = (xi + dx, yi + dir);
+                            let ep = tx >= 0 && ty >= 0 && self.en_passant == Some((tx as usize, ty as usize));
+                            if enemy(tx, ty) || ep {
+                                Self::push_pawn_move(&mut moves, (x, y), (tx as usize, ty as usize), white);
+                            }
+                        }
+                    }
+
+                    Some(Kind::Knight) | Some(Kind::King) => {
+                        let offsets = if kind(p) == Some(Kind::Knight) { KNIGHT_OFFSETS } else { KING_OFFSETS };
+                        for (dx, dy) in offsets {
+                            if free(xi + dx, yi + dy) || enemy(xi + dx, yi + dy) {
+                                push(&mut moves, x, y, xi + dx, yi + dy);
+                            }
+                        }
+                    }
+
+                    Some(k) => {
+                        let dirs: Vec<(i32, i32)> = match k {
+                            Kind::Bishop => DIAGONAL.to_vec(),
+                            Kind::Rook => STRAIGHT.to_vec(),
+                            _ => STRAIGHT.iter().chain(DIAGONAL.iter()).copied().collect()
+                        };
+
+                        for (dx, dy) in dirs {
+                            let (mut tx, mut ty) = (xi + dx, yi + dy);
+                            while free(tx, ty) {
+                                push(&mut moves, x, y, tx, ty);
+                                tx += dx;
+                                ty += dy;
+                            }
+
+                            if enemy(tx, ty) {
+                                push(&mut moves, x, y, tx, ty);
+                            }
+                        }
+                    }
+
+                    None => { }
+                }
+            }
+        }
+
+        let rank = if white { 0 } else { 7 };
+        let (king, rook) = if white { (Piece::WhiteKing, Piece::WhiteRook) } else { (Piece::BlackKing, Piece::BlackRook) };
+        let rights = if white { (self.castling[0], self.castling[1]) } else { (self.castling[2], self.castling[3]) };
+
+        if board[rank][4] == king && !is_attacked(board, 4, rank, !white) {
+            let row = &board[rank];
+
+            if rights.0 && row[7] == rook && row[5] == Piece::None && row[6] == Piece::None
+                && !is_attacked(board, 5, rank, !white) && !is_attacked(board, 6, rank, !white) {
+                push(&mut moves, 4, rank, 6, rank as i32);
+            }
+
+            if rights.1 && row[0] == rook && row[1] == Piece::None && row[2] == Piece::None && row[3] == Piece::None
+                && !is_attacked(board, 3, rank, !white) && !is_attacked(board, 2, rank, !white) {
+                push(&mut moves, 4, rank, 2, rank as i32);
+            }
+        }
+
+        return moves;
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        return self.pseudo_moves().into_iter().filter(|m| {
+            let mut next = self.clone();
+            next.apply(m);
+            return !in_check(&next.board, self.white_to_move);
+        }).collect();
+    }
+
+    // Applies a move without checking that it is legal.
+    pub fn apply(&mut self, m: &Move) {
+        let p = self.board[m.start_y][m.start_x];
+        let white = is_white(p);
+        let captured = self.board[m.end_y][m.end_x];
+
+        self.board[m.end_y][m.end_x] = p;
+        self.board[m.start_y][m.start_x] = Piece::None;
+
+        match kind(p) {
+            Some(Kind::Pawn) => {
+                if m.start_x != m.end_x && captured == Piece::None {
+                    self.board[m.start_y][m.end_x] = Piece::None;
+                }
+
+                if m.end_y == 0 || m.end_y == 7 {
+                    let k = match kind(m.promotion) {
+                        Some(k @ (Kind::Knight | Kind::Bishop | Kind::Rook)) => k,
+                        _ => Kind::Queen
+                    };
+                    self.board[m.end_y][m.end_x] = make(k, white);
+                }
+            }
+
+            Some(Kind::King) if m.start_x.abs_diff(m.end_x) == 2 => {
+                let (from, to) = if m.end_x == 6 { (7, 5) } else { (0, 3) };
+                self.board[m.end_y][to] = self.board[m.end_y][from];
+                self.board[m.end_y][from] = Piece::None;
+            }
+
+            _ => { }
+        }
+
+        self.en_passant = if kind(p) == Some(Kind::Pawn) && m.start_y.abs_diff(m.end_y) == 2 {
+            Some((m.start_x, (m.start_y + m.end_y) / 2))
+        } else {
+            None
+        };
+
+        for (x, y, rights) in [(4, 0, [0, 1]), (7, 0, [0, 0]), (0, 0, [1, 1]), (4, 7, [2, 3]), (7, 7, [2, 2]), (0, 7, [3, 3])] {
+            if (m.start_x, m.start_y) == (x, y) || (m.end_x, m.end_y) == (x, y) {
+                for r in rights {
+                    self.castling[r] = false;
+                }
+            }
+        }
+
+        self.halfmove_clock = if kind(p) == Some(Kind::Pawn) || captured != Piece::None { 0 } else { self.halfmove_clock + 1 };
+        if !white {
+            self.fullmove += 1;
+        }
+
+        self.white_to_move = !self.white_to_move;
+    }
+
+    // Plays the move if it is legal. A missing promotion piece means queen.
+    pub fn play(&mut self, m: &Move) -> bool {
+        let legal = self.legal_moves().into_iter().any(|l| {
+            (l.start_x, l.start_y, l.end_x, l.end_y) == (m.start_x, m.start_y, m.end_x, m.end_y)
+        });
+
+        if legal {
+            self.apply(m);
+        }
+
+        return legal;
+    }
+
+    pub fn result(&self) -> Joever {
+        if !self.legal_moves().is_empty() {
+            return Joever::Ongoing;
+        }
+
+        if !self.in_check() {
+            return Joever::Draw;
+        }
+
+        return if self.white_to_move { Joever::Black } else { Joever::White };
+    }
+}
+
+fn fen_char(piece: Piece) -> char {
+    let c = match kind(piece) {
+        Some(Kind::Pawn) => 'p',
+        Some(Kind::Knight) => 'n',
+        Some(Kind::Bishop) => 'b',
+        Some(Kind::Rook) => 'r',
+        Some(Kind::Queen) => 'q',
+        Some(Kind::King) => 'k',
+        None => ' '
+    };
+
+    return if is_white(piece) { c.to_ascii_uppercase() } else { c };
+}
+
+pub fn square_name(x: usize, y: usize) -> String {
+    return format!("{}{}", (b'a' + x as u8) as char, y + 1);
+}
+
+fn parse_square(s: &[u8]) -> Option<(usize, usize)> {
+    if s.len() != 2 || !(b'a'..=b'h').contains(&s[0]) || !(b'1'..=b'8').contains(&s[1]) {
+        return None;
+    }
+
+    return Some(((s[0] - b'a') as usize, (s[1] - b'1') as usize));
+}
+
+// Long algebraic notation as used by UCI, e.g. "e2e4" or "e7e8q".
+pub fn move_name(m: &Move) -> String {
+    let mut name = format!("{}{}", square_name(m.start_x, m.start_y), square_name(m.end_x, m.end_y));
+
+    if m.promotion != Piece::None {
+        name.push(fen_char(m.promotion).to_ascii_lowercase());
+    }
+
+    return name;
+}
+
+pub fn parse_move(name: &str, white: bool) -> Option<Move> {
+    let bytes = name.trim().as_bytes();
+    if bytes.len() != 4 && bytes.len() != 5 {
+        return None;
+    }
+
+    let (start_x, start_y) = parse_square(&bytes[0..2])?;
+    let (end_x, end_y) = parse_square(&bytes[2..4])?;
+
+    let promotion = match bytes.get(4) {
+        None => Piece::None,
+        Some(b'q') => make(Kind::Queen, white),
+        Some(b'r') => make(Kind::Rook, white),
+        Some(b'b') => make(Kind::Bishop, white),
+        Some(b'n') => make(Kind::Knight, white),
+        Some(_) => return None
+    };
+
+    return Some(Move{ start_x, start_y, end_x, end_y, promotion });
+}
+
+impl Position {
+    pub fn to_fen(&self) -> String {
+        let mut rows = vec![];
+
+        for y in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+
+            for x in 0..8 {
+                let p = self.board[y][x];
+                if p == Piece::None {
+                    empty += 1;
+                    continue;
+                }
+
+                if empty > 0 {
+                    row.push_str(&empty.to_string());
+                    empty = 0;
+                }
+
+                row.push(fen_char(p));
+            }
+
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+
+            rows.push(row);
+        }
+
+        let mut castling: String = ['K', 'Q', 'k', 'q'].iter()
+            .zip(self.castling.iter())
+            .filter(|(_, &allowed)| allowed)
+            .map(|(&c, _)| c)
+            .collect();
+
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some((x, y)) => square_name(x, y),
+            None => "-".to_string()
+        };
+
+        return format!("{} {} {} {} {} {}",
+            rows.join("/"),
+            if self.white_to_move { 'w' } else { 'b' },
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Kings on a1 and h8 unless placed elsewhere, so every position is legal.
+    fn position(pieces: &[(&str, Piece)], white_to_move: bool) -> Position {
+        let mut board = [[Piece::None; 8]; 8];
+        let kings = [("a1", Piece::WhiteKing), ("h8", Piece::BlackKing)];
+
+        for (square, piece) in kings.iter().filter(|(_, k)| !pieces.iter().any(|(_, p)| p == k)).chain(pieces.iter()) {
+            let (x, y) = parse_square(square.as_bytes()).unwrap();
+            board[y][x] = *piece;
+        }
+
+        return Position::from_board(board, white_to_move);
+    }
+
+    fn play(position: &mut Position, names: &[&str]) {
+        for name in names {
+            let m = parse_move(name, position.white_to_move).unwrap();
+            assert!(position.play(&m), "{} is not legal", name);
+        }
+    }
+
+    fn has_move(position: &Position, name: &str) -> bool {
+        return position.legal_moves().iter().any(|m| move_name(m) == name);
+    }
+
+    #[test]
+    fn castles_on_both_sides() {
+        let p = position(&[("e1", Piece::WhiteKing), ("h1", Piece::WhiteRook), ("a1", Piece::WhiteRook)], true);
+
+        assert!(has_move(&p, "e1g1"));
+        assert!(has_move(&p, "e1c1"));
+    }
+
+    #[test]
+    fn does_not_castle_through_check() {
+        let p = position(&[("e1", Piece::WhiteKing), ("h1", Piece::WhiteRook), ("a1", Piece::WhiteRook), ("f8", Piece::BlackRook)], true);
+
+        assert!(!has_move(&p, "e1g1"));
+        assert!(has_move(&p, "e1c1"));
+    }
+
+    #[test]
+    fn does_not_castle_out_of_check() {
+        let p = position(&[("e1", Piece::WhiteKing), ("h1", Piece::WhiteRook), ("a1", Piece::WhiteRook), ("e8", Piece::BlackRook)], true);
+
+        assert!(!has_move(&p, "e1g1"));
+        assert!(!has_move(&p, "e1c1"));
+    }
+
+    #[test]
+    fn castling_moves_the_rook() {
+        let mut p = Position::new();
+        play(&mut p, &["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1"]);
+
+        assert_eq!(p.board[0][6], Piece::WhiteKing);
+        assert_eq!(p.board[0][5], Piece::WhiteRook);
+        assert_eq!(p.board[0][7], Piece::None);
+        assert_eq!(p.castling, [false, false, true, true]);
+    }
+
+    #[test]
+    fn takes_en_passant_right_after_the_double_step() {
+        let mut p = Position::new();
+        play(&mut p, &["e2e4", "a7a6", "e4e5", "d7d5"]);
+
+        assert!(has_move(&p, "e5d6"));
+        play(&mut p, &["e5d6"]);
+        assert_eq!(p.board[4][3], Piece::None);
+        assert_eq!(p.board[5][3], Piece::WhitePawn);
+    }
+
+    #[test]
+    fn does_not_take_en_passant_later() {
+        let mut p = Position::new();
+        play(&mut p, &["e2e4", "d7d5", "e4e5", "a7a6"]);
+
+        assert!(!has_move(&p, "e5d6"));
+    }
+
+    #[test]
+    fn promotes_to_every_piece() {
+        let p = position(&[("b7", Piece::WhitePawn)], true);
+        let promotions: Vec<Piece> = p.legal_moves().into_iter().filter(|m| (m.start_x, m.start_y) == (1, 6)).map(|m| m.promotion).collect();
+
+        assert_eq!(promotions, vec![Piece::WhiteQueen, Piece::WhiteRook, Piece::WhiteBishop, Piece::WhiteKnight]);
+
+        let mut knight = p.clone();
+        play(&mut knight, &["b7b8n"]);
+        assert_eq!(knight.board[7][1], Piece::WhiteKnight);
+
+        // No piece given means a queen.
+        let mut queen = p.clone();
+        queen.apply(&Move{ start_x: 1, start_y: 6, end_x: 1, end_y: 7, promotion: Piece::None });
+        assert_eq!(queen.board[7][1], Piece::WhiteQueen);
+    }
+
+    #[test]
+    fn tells_stalemate_from_mate() {
+        let stalemate = position(&[("a8", Piece::BlackKing), ("b6", Piece::WhiteQueen), ("c1", Piece::WhiteKing)], false);
+        let mate = position(&[("a8", Piece::BlackKing), ("b7", Piece::WhiteQueen), ("c6", Piece::WhiteKing)], false);
+
+        assert_eq!(stalemate.result(), Joever::Draw);
+        assert_eq!(mate.result(), Joever::White);
+        assert_eq!(Position::new().result(), Joever::Ongoing);
+    }
+
+    #[test]
+    fn writes_fen() {
+        let mut p = Position::new();
+        assert_eq!(p.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+
+        play(&mut p, &["e2e4"]);
+        assert_eq!(p.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    }
+
+    #[test]
+    fn parses_move_names_back() {
+        for p in [Position::new(), position(&[("b7", Piece::WhitePawn)], true), position(&[("g2", Piece::BlackPawn)], false)] {
+            for m in p.legal_moves() {
+                assert_eq!(parse_move(&move_name(&m), p.white_to_move), Some(m));
+            }
+        }
+
+        assert_eq!(parse_move("e2e9", true), None);
+        assert_eq!(parse_move("e7e8x", true), None);
+    }
+}
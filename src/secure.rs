@@ -0,0 +1,174 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chess_network_protocol::*;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use rustls::{Certificate, ClientConfig, ClientConnection, PrivateKey, ServerConfig, ServerConnection, ServerName, StreamOwned};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use sha2::{Digest, Sha256};
+
+use crate::ext::{Extension, Incoming};
+use crate::wire::{self, Reader, Writer};
+
+// The host makes a new self-signed certificate every time it starts and
+// prints its fingerprint. Clients pin it with --tls-fingerprint. Without it
+// they refuse the certificate, unless --tls-insecure says to accept any and
+// print the one they got so it can be compared by hand.
+pub struct Fingerprint {
+    expected: Option<String>,
+    insecure: bool
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    return hex(&Sha256::digest(der));
+}
+
+fn hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    return io::Error::new(io::ErrorKind::Other, e);
+}
+
+pub fn server_config() -> io::Result<(Arc<ServerConfig>, String)> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    let der = cert.serialize_der().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    let key = cert.serialize_private_key_der();
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(der.clone())], PrivateKey(key))
+        .map_err(tls_error)?;
+
+    return Ok((Arc::new(config), fingerprint(&der)));
+}
+
+pub fn client_config(expected: Option<String>, insecure: bool) -> Arc<ClientConfig> {
+    let verifier = Fingerprint { expected: expected.map(|f| f.to_lowercase().replace(':', "")), insecure: insecure };
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    return Arc::new(config);
+}
+
+impl ServerCertVerifier for Fingerprint {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let got = fingerprint(&end_entity.0);
+
+        return match &self.expected {
+            Some(expected) if *expected != got => {
+                Err(rustls::Error::General(format!("certificate fingerprint {} does not match", got)))
+            }
+
+            Some(_) => Ok(ServerCertVerified::assertion()),
+
+            None if self.insecure => {
+                eprintln!("Host certificate fingerprint: {}", got);
+                Ok(ServerCertVerified::assertion())
+            }
+
+            None => {
+                Err(rustls::Error::General(format!("certificate fingerprint {} is not pinned, pass it with --tls-fingerprint or allow any with --tls-insecure", got)))
+            }
+        };
+    }
+}
+
+pub fn accept_tls(mut stream: TcpStream, config: &Arc<ServerConfig>) -> io::Result<(Reader, Writer)> {
+    let mut connection = ServerConnection::new(Arc::clone(config)).map_err(tls_error)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+
+    let tcp = stream.try_clone()?;
+    return wire::over_tls(Box::new(StreamOwned::new(connection, stream)), &tcp);
+}
+
+pub fn connect_tls(mut stream: TcpStream, config: &Arc<ClientConfig>) -> io::Result<(Reader, Writer)> {
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut connection = ClientConnection::new(Arc::clone(config), name).map_err(tls_error)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+
+    let tcp = stream.try_clone()?;
+    return wire::over_tls(Box::new(StreamOwned::new(connection, stream)), &tcp);
+}
+
+fn digest(nonce: &str, password: &str) -> String {
+    return hex(&Sha256::digest(format!("{}:{}", nonce, password).as_bytes()));
+}
+
+// Password protected hosts answer the client's handshake with a challenge
+// instead of their own handshake. Only a client that knows the password can
+// produce the response, and the password itself never goes over the wire.
+pub fn challenge(reader: &mut Reader, writer: &Writer, password: &str) -> io::Result<()> {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let nonce = hex(&bytes);
+
+    writer.write(&Extension::Challenge { nonce: nonce.clone() })?;
+
+    let expected = digest(&nonce, password);
+
+    return match reader.read::<Extension>()? {
+        Extension::Response { digest } if digest.len() == expected.len()
+            && digest.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0 => Ok(()),
+
+        _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "wrong password"))
+    };
+}
+
+// A host refuses a handshake it can not agree to with an error instead.
+fn handshake<T: DeserializeOwned>(message: Value) -> io::Result<T> {
+    if let Ok(ServerToClient::Error { board: _, moves: _, joever: _, message }) = serde_json::from_value(message.clone()) {
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused, message));
+    }
+
+    return Ok(serde_json::from_value(message)?);
+}
+
+// Reads the host's handshake, answering its challenge first if it sends one.
+pub fn read_handshake(reader: &mut Reader, writer: &Writer, password: Option<&str>) -> io::Result<ServerToClientHandshake> {
+    match handshake::<Incoming<ServerToClientHandshake>>(reader.read()?)? {
+        Incoming::Message(handshake) => { return Ok(handshake); }
+
+        Incoming::Extension(Extension::Challenge { nonce }) => {
+            let Some(password) = password else {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the host wants a password, pass it with --password=<secret>"));
+            };
+
+            writer.write(&Extension::Response { digest: digest(&nonce, password) })?;
+
+            // The host hangs up on a wrong password.
+            let message = reader.read().map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::PermissionDenied, "the host did not accept the password"),
+                _ => e
+            })?;
+
+            return handshake(message);
+        }
+
+        Incoming::Extension(_) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected message before the handshake"));
+        }
+    }
+}
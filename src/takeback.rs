@@ -0,0 +1,45 @@
+use crate::ext::Extension;
+use crate::net::Outbox;
+
+// Once both sides agreed on the `Takeback` extension either player may ask
+// to take back their last move. How far back that goes is decided by the
+// host when the request is accepted: only the asker's last move if the other
+// side has not answered it yet, otherwise the answer as well.
+pub struct Takeback {
+    pub enabled: bool,
+    // We asked and wait for the answer.
+    pub sent: bool,
+    // The other side asked and waits for ours.
+    pub asked: bool,
+    pub refused: bool
+}
+
+impl Takeback {
+    pub fn new() -> Takeback {
+        return Takeback { enabled: false, sent: false, asked: false, refused: false };
+    }
+
+    pub fn request(&mut self, outbox: &Outbox) {
+        if !self.enabled || self.sent {
+            return;
+        }
+
+        if outbox.send(&Extension::TakebackRequest).is_ok() {
+            self.sent = true;
+            self.refused = false;
+        }
+    }
+
+    pub fn refuse(&mut self, outbox: &Outbox) {
+        if self.asked {
+            let _ = outbox.send(&Extension::TakebackAnswer { accepted: false });
+            self.asked = false;
+        }
+    }
+
+    // Whatever was pending is settled.
+    pub fn done(&mut self) {
+        self.sent = false;
+        self.asked = false;
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chess_network_protocol::Move;
+
+use crate::rules::{self, Position};
+
+// How long an engine gets to exit after `quit` before it is killed.
+const QUIT_TIMEOUT: Duration = Duration::from_millis(500);
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A local engine speaking UCI on its stdin/stdout. Anything that answers
+// `uci`, `isready`, `position fen` and `go movetime` will do, so a small
+// script can stand in for a real engine.
+pub struct UciEngine {
+    pub name: String,
+    movetime: Duration,
+    // How long the engine gets to answer anything, on top of its move time.
+    timeout: Duration,
+    child: Child,
+    stdin: ChildStdin,
+    // Lines the engine wrote, read on their own thread so a silent engine
+    // can be given up on. Closed when the engine closes its output.
+    lines: Receiver<String>
+}
+
+impl UciEngine {
+    pub fn launch(path: &str, movetime: Duration) -> io::Result<UciEngine> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, lines) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            name: path.to_string(),
+            movetime: movetime,
+            timeout: ANSWER_TIMEOUT,
+            child: child,
+            stdin: stdin,
+            lines: lines
+        };
+
+        engine.send("uci")?;
+        let deadline = Instant::now() + engine.timeout;
+        loop {
+            let line = engine.read_line(deadline)?;
+
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+
+        engine.send("ucinewgame")?;
+        engine.wait_ready()?;
+
+        return Ok(engine);
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        return self.stdin.flush();
+    }
+
+    fn read_line(&mut self, deadline: Instant) -> io::Result<String> {
+        return match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(io::ErrorKind::TimedOut, "engine did not answer in time")),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "engine closed its output"))
+        };
+    }
+
+    fn wait_ready(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+
+        let deadline = Instant::now() + self.timeout;
+        while self.read_line(deadline)?.trim() != "readyok" { }
+
+        return Ok(());
+    }
+
+    // Returns None when the engine has no move, i.e. it is mated or stalemated.
+    // Unless `only` is empty the engine is limited to those moves.
+    pub fn best_move(&mut self, position: &Position, only: &[Move]) -> io::Result<Option<Move>> {
+        self.send(&format!("position fen {}", position.to_fen()))?;
+        self.wait_ready()?;
+
+        let mut go = format!("go movetime {}", self.movetime.as_millis());
+        if !only.is_empty() {
+            let names: Vec<String> = only.iter().map(rules::move_name).collect();
+            go = format!("{} searchmoves {}", go, names.join(" "));
+        }
+        self.send(&go)?;
+
+        let deadline = Instant::now() + self.movetime + self.timeout;
+        loop {
+            let line = self.read_line(deadline)?;
+            let mut words = line.split_whitespace();
+
+            if words.next() != Some("bestmove") {
+                continue;
+            }
+
+            return Ok(words.next().and_then(|m| rules::parse_move(m, position.white_to_move)));
+        }
+    }
+}
+
+// An engine that hangs instead of quitting is killed, dropping it never blocks
+// for longer than the timeout.
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        if self.send("quit").is_ok() {
+            let deadline = Instant::now() + QUIT_TIMEOUT;
+
+            while Instant::now() < deadline {
+                match self.child.try_wait() {
+                    Ok(Some(_)) => { return; }
+                    Ok(None) => { thread::sleep(Duration::from_millis(10)); }
+                    Err(_) => { break; }
+                }
+            }
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // Plays e2e4 unless it is limited to other moves, then it plays the last
+    // one it may. `quirks` are cases tried before the usual ones.
+    fn stand_in(name: &str, quirks: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("uci-{}-{}.sh", name, std::process::id()));
+
+        let script = format!(r#"#!/bin/sh
+while read line; do
+    case "$line" in
+        {}
+        uci) echo "id name Stand-in"; echo "uciok" ;;
+        isready) echo "readyok" ;;
+        *searchmoves*) echo "bestmove ${{line##* }}" ;;
+        go*) echo "bestmove e2e4" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#, quirks);
+
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        return path;
+    }
+
+    fn engine(name: &str, quirks: &str) -> UciEngine {
+        let path = stand_in(name, quirks);
+        let engine = UciEngine::launch(path.to_str().unwrap(), Duration::from_millis(10));
+
+        // The shell keeps the script open, it is not needed on disk any more.
+        fs::remove_file(&path).unwrap();
+        return engine.unwrap();
+    }
+
+    #[test]
+    fn launch_reads_the_name() {
+        assert_eq!(engine("name", "").name, "Stand-in");
+    }
+
+    #[test]
+    fn best_move_parses_the_answer() {
+        let mut engine = engine("best", "");
+        let m = engine.best_move(&Position::new(), &[]).unwrap();
+
+        assert_eq!(m, rules::parse_move("e2e4", true));
+    }
+
+    #[test]
+    fn best_move_can_be_limited() {
+        let mut engine = engine("limited", "");
+        let only = vec![rules::parse_move("d2d4", true).unwrap(), rules::parse_move("g1f3", true).unwrap()];
+        let m = engine.best_move(&Position::new(), &only).unwrap();
+
+        assert_eq!(m, rules::parse_move("g1f3", true));
+    }
+
+    #[test]
+    fn hung_engine_is_killed() {
+        let engine = engine("hang", "quit) ;;");
+        let start = Instant::now();
+        drop(engine);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn silent_engine_times_out() {
+        let mut engine = engine("silent", "go*) ;;");
+        engine.timeout = Duration::from_millis(200);
+
+        let start = Instant::now();
+        let error = engine.best_move(&Position::new(), &[]).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
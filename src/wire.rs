@@ -0,0 +1,468 @@
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tungstenite::{Message, WebSocket};
+
+use crate::record::Recorder;
+
+// Messages are plain concatenated JSON unless both sides agreed on the
+// `Framed` extension. A frame is a four byte big-endian length followed by
+// that many bytes of JSON. Frames are kept below 16 MiB so their first byte
+// is always zero, which no JSON value starts with. The reader can therefore
+// take either kind at any point and only the writer has to care which one
+// was negotiated.
+const MAX_FRAME: usize = 1 << 24;
+
+// Readers of shared streams give them up this often so writes can get through.
+const POLL: Duration = Duration::from_millis(20);
+
+// How long either side waits on the other during the handshake. Once the
+// game is on, waiting is up to the heartbeat.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub trait Stream: Read + Write + Send {}
+impl<S: Read + Write + Send> Stream for S {}
+
+// Over a WebSocket every protocol message is one text message, which already
+// marks where it ends, so framing does not apply there. TLS and WebSocket
+// streams are one object for both directions and are shared behind a lock.
+// A TLS reader waits on the TCP connection underneath without the lock.
+#[derive(Clone)]
+enum Transport {
+    Tcp(Arc<TcpStream>),
+    Tls(Arc<Mutex<Box<dyn Stream>>>, Arc<TcpStream>),
+    WebSocket(Arc<Mutex<WebSocket<TcpStream>>>)
+}
+
+pub struct Reader {
+    transport: Transport,
+    buffer: Vec<u8>,
+    // How long a read may wait for the other side, if it is limited.
+    timeout: Option<Duration>,
+    recorder: Option<Recorder>
+}
+
+// `socket` is the TCP connection under the transport, kept so the connection
+// can be closed while a reader is blocked on it.
+#[derive(Clone)]
+pub struct Writer {
+    transport: Transport,
+    socket: Arc<TcpStream>,
+    framed: Arc<AtomicBool>,
+    // Held for the whole of a write, so messages sent from different threads
+    // can not interleave.
+    sending: Arc<Mutex<()>>,
+    recorder: Option<Recorder>
+}
+
+fn pair(transport: Transport, socket: Arc<TcpStream>) -> (Reader, Writer) {
+    return (
+        Reader { transport: transport.clone(), buffer: vec![], timeout: None, recorder: None },
+        Writer { transport: transport, socket: socket, framed: Arc::new(AtomicBool::new(false)), sending: Arc::new(Mutex::new(())), recorder: None }
+    );
+}
+
+fn would_block(e: &io::Error) -> bool {
+    return matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut);
+}
+
+fn websocket_error(e: tungstenite::Error) -> io::Error {
+    return match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e)
+    };
+}
+
+// Runs the server side of the WebSocket handshake on a freshly accepted
+// connection.
+pub fn accept_websocket(stream: TcpStream) -> io::Result<(Reader, Writer)> {
+    let socket = tungstenite::accept(stream).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    socket.get_ref().set_read_timeout(Some(POLL))?;
+    let tcp = Arc::new(socket.get_ref().try_clone()?);
+
+    return Ok(pair(Transport::WebSocket(Arc::new(Mutex::new(socket))), tcp));
+}
+
+// `tcp` is the connection underneath `stream`. The TLS handshake has to be
+// done already, since from here on reads time out to let writes through.
+pub fn over_tls(stream: Box<dyn Stream>, tcp: &TcpStream) -> io::Result<(Reader, Writer)> {
+    tcp.set_read_timeout(Some(POLL))?;
+    let socket = Arc::new(tcp.try_clone()?);
+
+    return Ok(pair(Transport::Tls(Arc::new(Mutex::new(stream)), socket.clone()), socket));
+}
+
+pub fn over_tcp(stream: &TcpStream) -> io::Result<(Reader, Writer)> {
+    return Ok(pair(Transport::Tcp(Arc::new(stream.try_clone()?)), Arc::new(stream.try_clone()?)));
+}
+
+// Keeps a copy of every message going through the reader and the writer.
+pub fn record(reader: &mut Reader, writer: &mut Writer, recorder: &Recorder) {
+    let recorder = recorder.connection(writer.peer_addr().ok());
+    reader.recorder = Some(recorder.clone());
+    writer.recorder = Some(recorder);
+}
+
+impl Reader {
+    // Reads that wait longer than `timeout` fail with TimedOut. Shared streams
+    // already time out to let writes through, so they keep count themselves.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout = timeout;
+
+        if let Transport::Tcp(stream) = &self.transport {
+            stream.set_read_timeout(timeout)?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn read<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        // Read as it came in, so the recording shows what was sent even when
+        // it is not what we expected.
+        if let Some(recorder) = self.recorder.clone() {
+            let message: serde_json::Value = self.read_message()?;
+            recorder.received(&message);
+            return Ok(serde_json::from_value(message)?);
+        }
+
+        return self.read_message();
+    }
+
+    fn read_message<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        return match &self.transport {
+            Transport::Tcp(stream) => read_tcp(stream),
+            Transport::Tls(stream, socket) => read_buffered(&mut self.buffer, stream, socket, self.timeout),
+            Transport::WebSocket(socket) => read_websocket(socket, self.timeout)
+        };
+    }
+}
+
+fn read_tcp<T: DeserializeOwned>(mut stream: &TcpStream) -> io::Result<T> {
+    let mut first = [0u8; 1];
+
+    loop {
+        stream.read_exact(&mut first)?;
+        if !first[0].is_ascii_whitespace() {
+            break;
+        }
+    }
+
+    if first[0] == 0 {
+        let mut length = [0u8; 3];
+        stream.read_exact(&mut length)?;
+
+        let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload)?;
+
+        return Ok(serde_json::from_slice(&payload)?);
+    }
+
+    // Objects and strings end on their closing byte, so nothing after the
+    // message is read off the stream.
+    let mut de = serde_json::Deserializer::from_reader(Cursor::new(first).chain(stream));
+    return Ok(T::deserialize(&mut de)?);
+}
+
+// Takes the first whole message off the front of the buffer, if there is one.
+fn parse<T: DeserializeOwned>(buffer: &mut Vec<u8>) -> io::Result<Option<T>> {
+    let start = buffer.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(buffer.len());
+    buffer.drain(..start);
+
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+
+    if buffer[0] == 0 {
+        if buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes([0, buffer[1], buffer[2], buffer[3]]) as usize;
+        if buffer.len() < 4 + length {
+            return Ok(None);
+        }
+
+        let message = serde_json::from_slice(&buffer[4..4 + length])?;
+        buffer.drain(..4 + length);
+        return Ok(Some(message));
+    }
+
+    let mut messages = serde_json::Deserializer::from_slice(buffer).into_iter::<T>();
+
+    return match messages.next() {
+        Some(Ok(message)) => {
+            let used = messages.byte_offset();
+            buffer.drain(..used);
+            Ok(Some(message))
+        }
+
+        Some(Err(e)) if e.is_eof() => Ok(None),
+        Some(Err(e)) => Err(e.into()),
+        None => Ok(None)
+    };
+}
+
+fn timed_out(start: Instant, timeout: Option<Duration>) -> bool {
+    return timeout.map(|t| start.elapsed() >= t).unwrap_or(false);
+}
+
+fn read_buffered<T: DeserializeOwned>(buffer: &mut Vec<u8>, stream: &Mutex<Box<dyn Stream>>, socket: &TcpStream, timeout: Option<Duration>) -> io::Result<T> {
+    let mut chunk = [0u8; 4096];
+    let start = Instant::now();
+    // The stream may hold more than the last read took, then there is
+    // nothing to wait for on the socket.
+    let mut pending = true;
+
+    loop {
+        if let Some(message) = parse(buffer)? {
+            return Ok(message);
+        }
+
+        // Waiting is done on the socket, so writers only ever wait for a
+        // read that has something to read.
+        if !pending {
+            match socket.peek(&mut chunk[..1]) {
+                Ok(_) => { }
+                Err(e) if would_block(&e) && timed_out(start, timeout) => { return Err(e); }
+                Err(e) if would_block(&e) => { continue; }
+                Err(e) => { return Err(e); }
+            }
+        }
+
+        let read = stream.lock().unwrap().read(&mut chunk);
+
+        match read {
+            Ok(0) => { return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")); }
+
+            Ok(n) => {
+                buffer.extend_from_slice(&chunk[..n]);
+                pending = true;
+            }
+
+            Err(e) if would_block(&e) && timed_out(start, timeout) => { return Err(e); }
+            Err(e) if would_block(&e) => { pending = false; }
+            Err(e) => { return Err(e); }
+        }
+    }
+}
+
+fn read_websocket<T: DeserializeOwned>(socket: &Mutex<WebSocket<TcpStream>>, timeout: Option<Duration>) -> io::Result<T> {
+    let start = Instant::now();
+
+    loop {
+        let message = socket.lock().unwrap().read();
+
+        match message {
+            Ok(Message::Text(text)) => { return Ok(serde_json::from_str(&text)?); }
+
+            Ok(Message::Binary(bytes)) => { return Ok(serde_json::from_slice(&bytes)?); }
+
+            Ok(Message::Close(_)) => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "WebSocket closed"));
+            }
+
+            Ok(_) => { }
+
+            Err(tungstenite::Error::Io(e)) if would_block(&e) && timed_out(start, timeout) => { return Err(e); }
+            Err(tungstenite::Error::Io(e)) if would_block(&e) => { std::thread::yield_now(); }
+
+            Err(e) => { return Err(websocket_error(e)); }
+        }
+    }
+}
+
+impl Writer {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        return self.socket.peer_addr();
+    }
+
+    // Ends the connection in both directions, which also wakes up the reader.
+    pub fn shutdown(&self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+
+    pub fn set_framed(&self, framed: bool) {
+        self.framed.store(framed, Ordering::SeqCst);
+    }
+
+    pub fn write<T: Serialize>(&self, message: &T) -> io::Result<()> {
+        return self.write_json(serde_json::to_string(message)?);
+    }
+
+    // Sends one message that was already serialized.
+    pub fn write_json(&self, text: String) -> io::Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.sent(&text);
+        }
+
+        if let Transport::WebSocket(socket) = &self.transport {
+            return socket.lock().unwrap().send(Message::Text(text)).map_err(websocket_error);
+        }
+
+        let mut bytes = text.into_bytes();
+
+        if self.framed.load(Ordering::SeqCst) {
+            if bytes.len() >= MAX_FRAME {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "message too large for a frame"));
+            }
+
+            let mut frame = (bytes.len() as u32).to_be_bytes().to_vec();
+            frame.append(&mut bytes);
+            bytes = frame;
+        }
+
+        return match &self.transport {
+            Transport::Tcp(stream) => {
+                let _sending = self.sending.lock().unwrap();
+                (&**stream).write_all(&bytes)
+            }
+
+            Transport::Tls(stream, _) => {
+                let mut stream = stream.lock().unwrap();
+                stream.write_all(&bytes)?;
+                stream.flush()
+            }
+
+            Transport::WebSocket(_) => unreachable!()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // Both ends of a local connection.
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (host, _) = listener.accept().unwrap();
+        return (client, host);
+    }
+
+    // Plain TCP, and the buffered reader TLS uses over the same bytes.
+    fn readers(stream: &TcpStream) -> [Reader; 2] {
+        let (tcp, _) = over_tcp(stream).unwrap();
+        let (tls, _) = over_tls(Box::new(stream.try_clone().unwrap()), stream).unwrap();
+        return [tcp, tls];
+    }
+
+    fn frame(message: &Value) -> Vec<u8> {
+        let text = message.to_string();
+        let mut bytes = (text.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(text.as_bytes());
+        return bytes;
+    }
+
+    #[test]
+    fn websocket_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let host = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut reader, writer) = accept_websocket(stream).unwrap();
+
+            let message: Value = reader.read().unwrap();
+            writer.write(&json!({ "Move": message["Move"].clone() })).unwrap();
+            return message;
+        });
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", address)).unwrap();
+        let sent = json!({ "Move": { "start_x": 4, "start_y": 1, "end_x": 4, "end_y": 3, "promotion": "None" } });
+        socket.send(Message::Text(sent.to_string())).unwrap();
+
+        let answer = loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                break serde_json::from_str::<Value>(&text).unwrap();
+            }
+        };
+
+        assert_eq!(host.join().unwrap(), sent);
+        assert_eq!(answer, sent);
+    }
+
+    #[test]
+    fn framed_and_unframed_messages_mix() {
+        let messages: Vec<Value> = (0..4).map(|i| json!({ "Chat": { "message": format!("message {}", i) } })).collect();
+
+        let (mut client, host) = connection();
+
+        for mut reader in readers(&host) {
+            // Whitespace between messages, and both kinds written at once so
+            // the buffered reader gets them in one read.
+            let mut bytes = format!("{}\n", messages[0]).into_bytes();
+            bytes.extend(frame(&messages[1]));
+            bytes.extend(format!(" {}", messages[2]).into_bytes());
+            bytes.extend(frame(&messages[3]));
+            client.write_all(&bytes).unwrap();
+
+            for message in &messages {
+                assert_eq!(reader.read::<Value>().unwrap(), *message);
+            }
+        }
+    }
+
+    #[test]
+    fn framing_starts_after_the_features_ack() {
+        let (client, host) = connection();
+        let (_, writer) = over_tcp(&client).unwrap();
+
+        for mut reader in readers(&host) {
+            let ack = json!({ "Features": ["Framed"] });
+            let m = json!({ "Move": { "start_x": 4, "start_y": 1, "end_x": 4, "end_y": 3, "promotion": "None" } });
+
+            writer.set_framed(false);
+            writer.write(&ack).unwrap();
+            writer.set_framed(true);
+            writer.write(&m).unwrap();
+
+            assert_eq!(reader.read::<Value>().unwrap(), ack);
+            assert_eq!(reader.read::<Value>().unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_not_sent() {
+        let (client, _host) = connection();
+        let (_, writer) = over_tcp(&client).unwrap();
+        writer.set_framed(true);
+
+        let text = "x".repeat(MAX_FRAME);
+        let error = writer.write(&text).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writes_get_through_while_reading() {
+        let (client, host) = connection();
+        let (mut reader, writer) = over_tls(Box::new(client.try_clone().unwrap()), &client).unwrap();
+        let (mut peer, _) = over_tcp(&host).unwrap();
+
+        // The reader waits for the whole test, nothing is sent to it.
+        thread::spawn(move || {
+            let _ = reader.read::<Value>();
+        });
+
+        let start = Instant::now();
+        for i in 0..50 {
+            writer.write(&json!({ "Ping": { "id": i } })).unwrap();
+            assert_eq!(peer.read::<Value>().unwrap(), json!({ "Ping": { "id": i } }));
+        }
+
+        // Every write needs the lock the waiting reader shares.
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}